@@ -1,19 +1,188 @@
 
-use quote::{quote, ToTokens};
-use syn::{parse_macro_input, Attribute, DataStruct, DeriveInput, Ident, Generics};
+use quote::{format_ident, quote, ToTokens};
+use syn::{parse_macro_input, DataEnum, DataStruct, DeriveInput, Field, Fields, Ident, Generics, LitStr};
 
-fn has_attr(attrs: &Vec<Attribute>, query: &str) -> bool {
-    attrs.iter().any(|attr| attr.path().is_ident(query))
+/// The options set on a field using `#[no_serialize]` and `#[serialize(...)]`
+#[derive(Default)]
+struct FieldAttrs {
+    /// Don't serialize the field at all
+    skip: bool,
+    /// The key the field is stored under, if different from the field's name
+    rename: Option<LitStr>,
+    /// Old keys the field might be stored under in existing data
+    aliases: Vec<LitStr>,
+    /// The value to use when the field is missing or invalid. `Some(None)` means `Default::default()`
+    default: Option<Option<syn::Path>>,
+    /// A function deciding if the field should be left out when serializing
+    skip_if: Option<syn::Path>,
+    /// Serialize the field through its serde implementation instead of `Serializable`
+    with_serde: bool,
+    /// The integer key the field is stored under in the compact encoding
+    tag: Option<syn::LitInt>,
 }
 
-fn serializable_struct(strct: DataStruct, name: Ident, project_type: Option<syn::Type>, generics: Generics) -> proc_macro2::TokenStream {
+impl FieldAttrs {
 
-    // TODO: ensure struct fields are named
+    fn parse(field: &Field) -> syn::Result<Self> {
+        let mut result = Self::default();
+        for attr in &field.attrs {
+            if attr.path().is_ident("no_serialize") {
+                attr.meta.require_path_only()?;
+                result.skip = true;
+            }
+            if attr.path().is_ident("tag") {
+                let tag = &attr.meta.require_name_value()?.value;
+                let syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(tag), .. }) = tag else {
+                    return Err(syn::Error::new_spanned(tag, "expected an integer tag"));
+                };
+                tag.base10_parse::<u64>()?;
+                result.tag = Some(tag.clone());
+            }
+            if attr.path().is_ident("serialize") {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("skip") {
+                        result.skip = true;
+                    } else if meta.path.is_ident("rename") {
+                        result.rename = Some(meta.value()?.parse()?);
+                    } else if meta.path.is_ident("alias") {
+                        result.aliases.push(meta.value()?.parse()?);
+                    } else if meta.path.is_ident("default") {
+                        result.default = Some(if meta.input.peek(syn::Token![=]) { Some(meta.value()?.parse()?) } else { None });
+                    } else if meta.path.is_ident("skip_if") {
+                        result.skip_if = Some(meta.value()?.parse()?);
+                    } else if meta.path.is_ident("with_serde") {
+                        result.with_serde = true;
+                    } else {
+                        return Err(meta.error("unknown serialize attribute. expected `skip`, `rename`, `alias`, `default`, `skip_if` or `with_serde`"));
+                    }
+                    Ok(())
+                })?;
+            }
+        }
 
-    let serializable_fields = strct.fields.iter().filter(|field| !has_attr(&field.attrs, "no_serialize"));
-    let serializable_field_names = serializable_fields.clone().map(|field| field.ident.as_ref().unwrap());
-    let serializable_field_types = serializable_fields.clone().map(|field| field.ty.to_token_stream()); 
-    let serializable_field_names_2 = serializable_field_names.clone(); 
+        // Tuple fields are stored by position, so they can't be renamed or left out conditionally
+        if field.ident.is_none() {
+            if let Some(rename) = &result.rename {
+                return Err(syn::Error::new_spanned(rename, "`rename` is only supported on named fields"));
+            }
+            if let Some(alias) = result.aliases.first() {
+                return Err(syn::Error::new_spanned(alias, "`alias` is only supported on named fields"));
+            }
+            if let Some(skip_if) = &result.skip_if {
+                return Err(syn::Error::new_spanned(skip_if, "`skip_if` is only supported on named fields"));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// The key a named field is stored under
+    fn key(&self, field: &Field) -> LitStr {
+        let name = field.ident.as_ref().unwrap();
+        self.rename.clone().unwrap_or_else(|| LitStr::new(&name.to_string(), name.span()))
+    }
+
+    /// The integer tag of the field, if it is stored in the compact encoding
+    fn tag(&self) -> Option<u64> {
+        self.tag.as_ref().map(|tag| tag.base10_parse().unwrap())
+    }
+
+    /// Code for looking up a named field in a MessagePack map, checking the aliases if the field is not under its key.
+    /// Fields with a tag are also looked up under their key, so data written before switching to the compact encoding can still be read.
+    fn lookup(&self, field: &Field, data: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let key = self.key(field);
+        let aliases = &self.aliases;
+        let tag_lookup = self.tag().map(|tag| quote! { alisa::rmpv_get_tag(#data, #tag).or_else(|| alisa::rmpv_get(#data, #key)) });
+        let key_lookup = tag_lookup.unwrap_or_else(|| quote! { alisa::rmpv_get(#data, #key) });
+        quote! {
+            #key_lookup #(.or_else(|| alisa::rmpv_get(#data, #aliases)))*
+        }
+    }
+
+    /// Code for the default value of the field, if one was given
+    fn default_value(&self) -> Option<proc_macro2::TokenStream> {
+        match &self.default {
+            Some(Some(path)) => Some(quote! { #path() }),
+            Some(None) => Some(quote! { Default::default() }),
+            None => None
+        }
+    }
+
+    /// Code deserializing the field's value. `deserialize` generates the code for deserializing a value of a given type
+    fn deserialize_value(&self, ty: &syn::Type, deserialize: impl FnOnce(proc_macro2::TokenStream) -> proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        if self.with_serde {
+            let value = deserialize(quote! { alisa::SerdeValue<#ty> });
+            quote! { #value.map(alisa::SerdeValue::into_inner) }
+        } else {
+            deserialize(quote! { #ty })
+        }
+    }
+
+    /// Code serializing the field's value
+    fn serialize_value(&self, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        if self.with_serde {
+            quote! { alisa::SerdeValue::serialize_value(&#value) }
+        } else {
+            quote! { #value.serialize(context) }
+        }
+    }
+
+    /// Code pushing the field's entry onto `fields`, the list of entries of the MessagePack map being serialized
+    fn push_entry(&self, field: &Field, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let key = match self.tag() {
+            Some(tag) => quote! { #tag },
+            None => self.key(field).into_token_stream()
+        };
+        let serialized = self.serialize_value(value.clone());
+        let push = quote! {
+            fields.push((#key.into(), #serialized));
+        };
+        match &self.skip_if {
+            Some(skip_if) => quote! {
+                if !#skip_if(&#value) {
+                    #push
+                }
+            },
+            None => push
+        }
+    }
+
+}
+
+/// The options set on an enum variant using `#[serialize(...)]`
+#[derive(Default)]
+struct VariantAttrs {
+    /// The name the variant is stored under, if different from the variant's name
+    rename: Option<LitStr>,
+    /// Old names the variant might be stored under in existing data
+    aliases: Vec<LitStr>,
+}
+
+impl VariantAttrs {
+
+    fn parse(variant: &syn::Variant) -> syn::Result<Self> {
+        let mut result = Self::default();
+        for attr in &variant.attrs {
+            if attr.path().is_ident("serialize") {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("rename") {
+                        result.rename = Some(meta.value()?.parse()?);
+                    } else if meta.path.is_ident("alias") {
+                        result.aliases.push(meta.value()?.parse()?);
+                    } else {
+                        return Err(meta.error("unknown serialize attribute for enum variant. expected `rename` or `alias`"));
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+        Ok(result)
+    }
+
+}
+
+/// Get the generics for the impl block and the generic arguments for `Serializable<...>`/`DeserializationContext<...>`
+fn impl_generics(project_type: Option<syn::Type>, generics: &Generics) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
     let impl_generic = if project_type.is_none() {
         quote! { <P: alisa::Project, #generics > }
     } else {
@@ -24,46 +193,394 @@ fn serializable_struct(strct: DataStruct, name: Ident, project_type: Option<syn:
     } else {
         quote! { <P> }
     };
+    (impl_generic, context_generic)
+}
+
+fn parse_field_attrs(fields: &Fields) -> syn::Result<Vec<FieldAttrs>> {
+    fields.iter().map(FieldAttrs::parse).collect()
+}
+
+/// The options set on a type using `#[serialize(...)]`
+#[derive(Default)]
+struct ContainerAttrs {
+    /// Store named fields under their integer tags instead of their names
+    compact: Option<Ident>,
+}
+
+impl ContainerAttrs {
+
+    fn parse(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut result = Self::default();
+        for attr in attrs {
+            if attr.path().is_ident("serialize") {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("compact") {
+                        result.compact = meta.path.get_ident().cloned();
+                    } else {
+                        return Err(meta.error("unknown serialize attribute. expected `compact`"));
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+        Ok(result)
+    }
+
+}
+
+/// Make sure the tags of the fields of a compact struct are present and unique, or that there are no tags if the struct isn't compact
+fn check_field_tags(fields: &Fields, field_attrs: &[FieldAttrs], compact: bool) -> syn::Result<()> {
+    let mut tags = std::collections::HashSet::new();
+    for (field, attrs) in fields.iter().zip(field_attrs.iter()) {
+        match (&attrs.tag, compact) {
+            (Some(tag), false) => return Err(syn::Error::new_spanned(tag, "`tag` requires `#[serialize(compact)]` on the type")),
+            (Some(tag), true) => {
+                if !tags.insert(attrs.tag().unwrap()) {
+                    return Err(syn::Error::new_spanned(tag, "duplicate tag"));
+                }
+            },
+            (None, true) if !attrs.skip => return Err(syn::Error::new_spanned(field, "fields of a compact struct need a `#[tag = N]`")),
+            (None, _) => {}
+        }
+    }
+    Ok(())
+}
+
+fn serializable_struct(strct: DataStruct, name: Ident, container_attrs: ContainerAttrs, project_type: Option<syn::Type>, generics: Generics) -> syn::Result<proc_macro2::TokenStream> {
+
+    let field_attrs = parse_field_attrs(&strct.fields)?;
+    if let Some(compact) = &container_attrs.compact {
+        if !matches!(strct.fields, Fields::Named(_)) {
+            return Err(syn::Error::new_spanned(compact, "`compact` is only supported on structs with named fields"));
+        }
+    }
+    check_field_tags(&strct.fields, &field_attrs, container_attrs.compact.is_some())?;
+
+    let (impl_generic, context_generic) = impl_generics(project_type, &generics);
+    let generics_names = generics.type_params().map(|param| &param.ident);
+
+    let (deserialize_body, serialize_body) = match &strct.fields {
+        Fields::Named(fields) => {
+            let serializable_fields = fields.named.iter().zip(field_attrs.iter()).filter(|(_, attrs)| !attrs.skip);
+            let field_deserializations = serializable_fields.clone().map(|(field, attrs)| {
+                let name = field.ident.as_ref().unwrap();
+                let ty = &field.ty;
+                let lookup = attrs.lookup(field, quote! { data });
+                let fallback = attrs.default_value().map(|default| quote! {
+                    else {
+                        result.#name = #default;
+                    }
+                });
+                let key = attrs.key(field);
+                let deserialization = attrs.deserialize_value(ty, |ty| quote! { #lookup.and_then(|value| context.deserialize_field::<#ty>(#key, value)) });
+                quote! {
+                    if let Some(value) = #deserialization {
+                        result.#name = value;
+                    } #fallback
+                }
+            });
+            let field_entries = serializable_fields.map(|(field, attrs)| {
+                let name = field.ident.as_ref().unwrap();
+                attrs.push_entry(field, quote! { self.#name })
+            });
+            (
+                quote! {
+                    if !data.is_map() {
+                        context.expected::<()>("map of fields", data);
+                    }
+                    let mut result = Self::default();
+                    #(#field_deserializations)*
+                    Some(result)
+                },
+                quote! {
+                    #[allow(unused_mut)]
+                    let mut fields = Vec::new();
+                    #(#field_entries)*
+                    alisa::rmpv::Value::Map(fields)
+                }
+            )
+        },
+        Fields::Unnamed(fields) => {
+            // Tuple structs are encoded as an array of their serializable fields
+            let serializable_fields = fields.unnamed.iter().zip(field_attrs.iter()).enumerate().filter(|(_, (_, attrs))| !attrs.skip);
+            let field_serializations = serializable_fields.clone().map(|(idx, (_, attrs))| {
+                let idx = syn::Index::from(idx);
+                attrs.serialize_value(quote! { self.#idx })
+            });
+            let field_deserializations = serializable_fields.clone().enumerate().map(|(array_idx, (idx, (field, attrs)))| {
+                let idx = syn::Index::from(idx);
+                let ty = &field.ty;
+                let fallback = attrs.default_value().map(|default| quote! {
+                    else {
+                        result.#idx = #default;
+                    }
+                });
+                let deserialization = attrs.deserialize_value(ty, |ty| quote! { data.as_array().and_then(|fields| fields.get(#array_idx)).and_then(|value| context.deserialize_index::<#ty>(#array_idx, value)) });
+                quote! {
+                    if let Some(value) = #deserialization {
+                        result.#idx = value;
+                    } #fallback
+                }
+            });
+            (
+                quote! {
+                    if !data.is_array() {
+                        context.expected::<()>("array of fields", data);
+                    }
+                    let mut result = Self::default();
+                    #(#field_deserializations)*
+                    Some(result)
+                },
+                quote! {
+                    alisa::rmpv::Value::Array(vec![
+                        #(#field_serializations, )*
+                    ])
+                }
+            )
+        },
+        Fields::Unit => (
+            quote! {
+                Some(Self)
+            },
+            quote! {
+                alisa::rmpv::Value::Nil
+            }
+        )
+    };
+
+    Ok(quote! {
+        impl #impl_generic alisa::Serializable #context_generic for #name <#(#generics_names, )*> {
+
+            #[allow(unused_variables)]
+            fn deserialize(data: &alisa::rmpv::Value, context: &mut alisa::DeserializationContext #context_generic) -> Option<Self> {
+                #deserialize_body
+            }
+
+            #[allow(unused_variables)]
+            fn serialize(&self, context: &alisa::SerializationContext #context_generic) -> alisa::rmpv::Value {
+                #serialize_body
+            }
+
+        }
+    })
+}
+
+/// Generate the `match` arms for serializing and deserializing a single enum variant.
+/// Unit variants are encoded as the variant name, while tuple and struct variants are encoded as a map with a single entry from the variant name to the variant's fields.
+fn serializable_enum_variant(variant: &syn::Variant) -> syn::Result<(proc_macro2::TokenStream, proc_macro2::TokenStream)> {
+    let variant_name = &variant.ident;
+    let variant_attrs = VariantAttrs::parse(variant)?;
+    let field_attrs = parse_field_attrs(&variant.fields)?;
+    check_field_tags(&variant.fields, &field_attrs, false)?;
+
+    let key = variant_attrs.rename.unwrap_or_else(|| LitStr::new(&variant_name.to_string(), variant_name.span()));
+    let aliases = variant_attrs.aliases;
+    let names = quote! { #key #(| #aliases)* };
+
+    Ok(match &variant.fields {
+        Fields::Unit => (
+            quote! {
+                Self::#variant_name => #key.into(),
+            },
+            quote! {
+                #names => Some(Self::#variant_name),
+            }
+        ),
+        Fields::Unnamed(fields) => {
+            let bindings = (0..fields.unnamed.len()).map(|idx| format_ident!("field_{}", idx)).collect::<Vec<_>>();
+            let binding_serializations = field_attrs.iter().zip(bindings.iter())
+                .filter(|(attrs, _)| !attrs.skip)
+                .map(|(attrs, binding)| attrs.serialize_value(quote! { #binding }));
+            let mut field_idx = 0usize;
+            let field_values = fields.unnamed.iter().zip(field_attrs.iter()).map(|(field, attrs)| {
+                if attrs.skip {
+                    return quote! { Default::default() };
+                }
+                let ty = &field.ty;
+                let idx = field_idx;
+                field_idx += 1;
+                let value = attrs.deserialize_value(ty, |ty| quote! { fields.get(#idx).and_then(|value| context.deserialize_index::<#ty>(#idx, value)) });
+                match attrs.default_value() {
+                    Some(default) => quote! { #value.unwrap_or_else(|| #default) },
+                    None => quote! { #value? }
+                }
+            }).collect::<Vec<_>>();
+            (
+                quote! {
+                    Self::#variant_name(#(#bindings, )*) => alisa::rmpv::Value::Map(vec![
+                        (#key.into(), alisa::rmpv::Value::Array(vec![#(#binding_serializations, )*]))
+                    ]),
+                },
+                quote! {
+                    #names => {
+                        let Some(fields) = payload.and_then(alisa::rmpv::Value::as_array) else {
+                            context.error(format!("expected array of fields for variant `{}`", variant));
+                            return None;
+                        };
+                        Some(Self::#variant_name(#(#field_values, )*))
+                    },
+                }
+            )
+        },
+        Fields::Named(fields) => {
+            let field_names = fields.named.iter().map(|field| field.ident.as_ref().unwrap()).collect::<Vec<_>>();
+            let field_entries = fields.named.iter().zip(field_attrs.iter())
+                .filter(|(_, attrs)| !attrs.skip)
+                .map(|(field, attrs)| {
+                    let name = field.ident.as_ref().unwrap();
+                    attrs.push_entry(field, quote! { #name })
+                });
+            let field_values = fields.named.iter().zip(field_attrs.iter()).map(|(field, attrs)| {
+                let field_name = field.ident.as_ref().unwrap();
+                if attrs.skip {
+                    return quote! { #field_name: Default::default() };
+                }
+                let ty = &field.ty;
+                let lookup = attrs.lookup(field, quote! { payload });
+                let key = attrs.key(field);
+                let value = attrs.deserialize_value(ty, |ty| quote! { #lookup.and_then(|value| context.deserialize_field::<#ty>(#key, value)) });
+                match attrs.default_value() {
+                    Some(default) => quote! { #field_name: #value.unwrap_or_else(|| #default) },
+                    None => quote! { #field_name: #value? }
+                }
+            });
+            (
+                quote! {
+                    Self::#variant_name { #(#field_names, )* } => {
+                        #[allow(unused_mut)]
+                        let mut fields = Vec::new();
+                        #(#field_entries)*
+                        alisa::rmpv::Value::Map(vec![
+                            (#key.into(), alisa::rmpv::Value::Map(fields))
+                        ])
+                    },
+                },
+                quote! {
+                    #names => {
+                        let Some(payload) = payload.filter(|payload| payload.is_map()) else {
+                            context.error(format!("expected map of fields for variant `{}`", variant));
+                            return None;
+                        };
+                        Some(Self::#variant_name { #(#field_values, )* })
+                    },
+                }
+            )
+        }
+    })
+}
+
+fn serializable_enum(enm: DataEnum, name: Ident, project_type: Option<syn::Type>, generics: Generics) -> syn::Result<proc_macro2::TokenStream> {
+
+    let (serialize_arms, deserialize_arms): (Vec<_>, Vec<_>) = enm.variants.iter().map(serializable_enum_variant).collect::<syn::Result<Vec<_>>>()?.into_iter().unzip();
+    let (impl_generic, context_generic) = impl_generics(project_type, &generics);
     let generics_names = generics.type_params().map(|param| &param.ident);
 
-    quote! {
+    Ok(quote! {
         impl #impl_generic alisa::Serializable #context_generic for #name <#(#generics_names, )*> {
 
+            #[allow(unused_variables)]
             fn deserialize(data: &alisa::rmpv::Value, context: &mut alisa::DeserializationContext #context_generic) -> Option<Self> {
-                let mut result = Self::default();
-                #(
-                    if let Some(value) = alisa::rmpv_get(data, stringify!(#serializable_field_names_2)) {
-                        if let Some(value) = <#serializable_field_types>::deserialize(value, context) { 
-                            result.#serializable_field_names_2 = value;
-                        }
+                let (variant, payload) = match data {
+                    alisa::rmpv::Value::String(variant) => (variant.as_str(), None),
+                    alisa::rmpv::Value::Map(entries) if entries.len() == 1 => (entries[0].0.as_str(), Some(&entries[0].1)),
+                    _ => (None, None)
+                };
+                let Some(variant) = variant else {
+                    return context.expected("enum variant", data);
+                };
+                match variant {
+                    #(#deserialize_arms)*
+                    // Unknown variants (e.g. from a newer version of the file format) are reported like any other invalid value,
+                    // so a struct holding the enum keeps its default value for the field
+                    variant => {
+                        context.error(format!("unknown variant `{}`", variant));
+                        None
                     }
-                )*
-                Some(result)
+                }
             }
 
+            #[allow(unused_variables)]
             fn serialize(&self, context: &alisa::SerializationContext #context_generic) -> alisa::rmpv::Value {
-                alisa::rmpv::Value::Map(vec![
-                    #((stringify!(#serializable_field_names).into(), self.#serializable_field_names.serialize(context)), )*
-                ])
+                match self {
+                    #(#serialize_arms)*
+                }
             }
 
         }
-    }
+    })
 }
 
-#[proc_macro_derive(Serializable, attributes(project, no_serialize))]
-pub fn serializable(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let input = parse_macro_input!(input as DeriveInput);
-    
+fn derive_serializable(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let project_attribute = input.attrs.iter().filter(
         |a| a.path().segments.len() == 1 && a.path().segments[0].ident == "project"
     ).nth(0);
-    let project_type = project_attribute.map(|attr| attr.parse_args::<syn::Type>().expect("expected project type!"));
+    let project_type = project_attribute.map(|attr| attr.parse_args::<syn::Type>()).transpose()?;
+    let container_attrs = ContainerAttrs::parse(&input.attrs)?;
 
     match input.data {
-        syn::Data::Struct(data_struct) => serializable_struct(data_struct, input.ident, project_type, input.generics),
-        syn::Data::Enum(_data_enum) => todo!(),
-        syn::Data::Union(_data_union) => panic!("cannot serialize union."),
-    }.into()
+        syn::Data::Struct(data_struct) => serializable_struct(data_struct, input.ident, container_attrs, project_type, input.generics),
+        syn::Data::Enum(data_enum) => {
+            if let Some(compact) = &container_attrs.compact {
+                return Err(syn::Error::new_spanned(compact, "`compact` is only supported on structs with named fields"));
+            }
+            serializable_enum(data_enum, input.ident, project_type, input.generics)
+        },
+        syn::Data::Union(data_union) => Err(syn::Error::new_spanned(data_union.union_token, "cannot derive Serializable for a union")),
+    }
+}
+
+#[proc_macro_derive(Serializable, attributes(project, no_serialize, serialize, tag))]
+pub fn serializable(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    derive_serializable(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::derive_serializable;
+
+    fn derive_error(input: syn::DeriveInput) -> String {
+        derive_serializable(input).expect_err("expected the derive to fail").to_string()
+    }
+
+    #[test]
+    fn compact_structs_need_unique_tags() {
+        assert_eq!(derive_error(syn::parse_quote! {
+            #[serialize(compact)]
+            struct StrokePoint {
+                #[tag = 0]
+                x: f32,
+                #[tag = 0]
+                y: f32
+            }
+        }), "duplicate tag");
+        assert_eq!(derive_error(syn::parse_quote! {
+            #[serialize(compact)]
+            struct StrokePoint {
+                #[tag = 0]
+                x: f32,
+                y: f32
+            }
+        }), "fields of a compact struct need a `#[tag = N]`");
+        assert_eq!(derive_error(syn::parse_quote! {
+            struct StrokePoint {
+                #[tag = 0]
+                x: f32
+            }
+        }), "`tag` requires `#[serialize(compact)]` on the type");
+
+        // Skipped fields aren't stored, so they don't need a tag
+        assert!(derive_serializable(syn::parse_quote! {
+            #[serialize(compact)]
+            struct StrokePoint {
+                #[tag = 0]
+                x: f32,
+                #[tag = 1]
+                y: f32,
+                #[no_serialize]
+                selected: bool
+            }
+        }).is_ok());
+    }
+
 }
- 
\ No newline at end of file
@@ -0,0 +1,297 @@
+
+use std::{collections::{HashMap, HashSet}, marker::PhantomData};
+
+use crate::{Client, Error, Object, Project, Ptr};
+
+/// The way an object changed since the last tick
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Change {
+    /// The object was added to the client, either by being created or by being loaded
+    Created,
+    Modified,
+    Deleted,
+    /// The object was unloaded from memory. It still exists, and can be loaded again.
+    Unloaded
+}
+
+/// The encoded state of an object before it changed, or `None` if it didn't exist, along with how it had changed before that
+type Snapshot = (Option<rmpv::Value>, Option<Change>);
+
+/// The changes to the objects of a single kind since the last tick
+#[derive(Default)]
+pub(crate) struct KindChanges {
+    created: HashSet<u64>,
+    modified: HashSet<u64>,
+    deleted: HashSet<u64>,
+    unloaded: HashSet<u64>,
+    /// While rolling back, the state of each object from before it first changed
+    snapshots: Option<HashMap<u64, Snapshot>>
+}
+
+impl KindChanges {
+
+    pub(crate) fn record_created(&mut self, key: u64) {
+        // An object deleted and recreated within the same tick (e.g. during collab rollback) was just modified
+        if self.deleted.remove(&key) || self.unloaded.remove(&key) {
+            self.modified.insert(key);
+        } else {
+            self.created.insert(key);
+        }
+    }
+
+    pub(crate) fn record_modified(&mut self, key: u64) {
+        if !self.created.contains(&key) {
+            self.modified.insert(key);
+        }
+    }
+
+    pub(crate) fn record_deleted(&mut self, key: u64) {
+        self.modified.remove(&key);
+        // An object created and deleted within the same tick never existed as far as the outside world is concerned
+        if !self.created.remove(&key) {
+            self.deleted.insert(key);
+        }
+    }
+
+    pub(crate) fn record_unloaded(&mut self, key: u64) {
+        self.modified.remove(&key);
+        if !self.created.remove(&key) {
+            self.unloaded.insert(key);
+        }
+    }
+
+    /// Start keeping the state of objects from before they change, so changes that end up undone can be forgotten
+    pub(crate) fn start_snapshots(&mut self) {
+        self.snapshots = Some(HashMap::new());
+    }
+
+    /// Does the state of an object need to be kept before it changes?
+    pub(crate) fn needs_snapshot(&self, key: u64) -> bool {
+        self.snapshots.as_ref().is_some_and(|snapshots| !snapshots.contains_key(&key))
+    }
+
+    /// Keep the state of an object from before it changes. `data` is `None` if the object doesn't exist.
+    pub(crate) fn snapshot(&mut self, key: u64, data: Option<rmpv::Value>) {
+        let change = self.change(key);
+        if let Some(snapshots) = &mut self.snapshots {
+            snapshots.entry(key).or_insert((data, change));
+        }
+    }
+
+    pub(crate) fn take_snapshots(&mut self) -> HashMap<u64, Snapshot> {
+        self.snapshots.take().unwrap_or_default()
+    }
+
+    /// Go back to reporting an object as changed in the given way
+    pub(crate) fn restore(&mut self, key: u64, change: Option<Change>) {
+        self.created.remove(&key);
+        self.modified.remove(&key);
+        self.deleted.remove(&key);
+        self.unloaded.remove(&key);
+        match change {
+            Some(Change::Created) => self.created.insert(key),
+            Some(Change::Modified) => self.modified.insert(key),
+            Some(Change::Deleted) => self.deleted.insert(key),
+            Some(Change::Unloaded) => self.unloaded.insert(key),
+            None => false
+        };
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.modified.is_empty() && self.deleted.is_empty() && self.unloaded.is_empty()
+    }
+
+    fn change(&self, key: u64) -> Option<Change> {
+        if self.created.contains(&key) {
+            Some(Change::Created)
+        } else if self.modified.contains(&key) {
+            Some(Change::Modified)
+        } else if self.deleted.contains(&key) {
+            Some(Change::Deleted)
+        } else if self.unloaded.contains(&key) {
+            Some(Change::Unloaded)
+        } else {
+            None
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (u64, Change)> + '_ {
+        self.created.iter().map(|key| (*key, Change::Created))
+            .chain(self.modified.iter().map(|key| (*key, Change::Modified)))
+            .chain(self.deleted.iter().map(|key| (*key, Change::Deleted)))
+            .chain(self.unloaded.iter().map(|key| (*key, Change::Unloaded)))
+    }
+
+}
+
+/// Everything that changed in a client since the last tick, including changes caused by operations from other collaborators.
+/// Returned by `Client::tick`.
+pub struct ChangeSet<P: Project> {
+    objects: HashMap<&'static str, KindChanges>,
+    project_modified: bool,
+    /// The problem that stopped the changes from being saved to the project file, if any
+    save_error: Option<Error>,
+    _marker: PhantomData<P>
+}
+
+impl<P: Project> ChangeSet<P> {
+
+    pub(crate) fn new(project_modified: bool) -> Self {
+        Self {
+            objects: HashMap::new(),
+            project_modified,
+            save_error: None,
+            _marker: PhantomData
+        }
+    }
+
+    pub(crate) fn set_save_error(&mut self, error: Error) {
+        self.save_error = Some(error);
+    }
+
+    pub(crate) fn take_save_error(&mut self) -> Option<Error> {
+        self.save_error.take()
+    }
+
+    pub(crate) fn add_kind(&mut self, name: &'static str, changes: KindChanges) {
+        if !changes.is_empty() {
+            self.objects.insert(name, changes);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.project_modified && self.objects.is_empty()
+    }
+
+    /// Was the project's own data modified?
+    pub fn project_modified(&self) -> bool {
+        self.project_modified
+    }
+
+    /// If saving to the project file failed in this tick, the reason why.
+    /// The changes that couldn't be saved are kept in memory and saving them is retried on the next tick, so this should be shown to the user as a warning.
+    pub fn save_error(&self) -> Option<&Error> {
+        self.save_error.as_ref()
+    }
+
+    /// Get how an object changed, if at all
+    pub fn change<O: Object<Project = P>>(&self, ptr: Ptr<O>) -> Option<Change> {
+        self.objects.get(O::NAME)?.change(ptr.key)
+    }
+
+    /// Iterate over all the changed objects of a kind
+    pub fn changes<O: Object<Project = P>>(&self) -> impl Iterator<Item = (Ptr<O>, Change)> + '_ {
+        self.objects.get(O::NAME).into_iter().flat_map(KindChanges::iter).map(|(key, change)| (Ptr::from_key(key), change))
+    }
+
+    pub fn created<O: Object<Project = P>>(&self) -> impl Iterator<Item = Ptr<O>> + '_ {
+        self.changes::<O>().filter(|(_, change)| *change == Change::Created).map(|(ptr, _)| ptr)
+    }
+
+    pub fn modified<O: Object<Project = P>>(&self) -> impl Iterator<Item = Ptr<O>> + '_ {
+        self.changes::<O>().filter(|(_, change)| *change == Change::Modified).map(|(ptr, _)| ptr)
+    }
+
+    pub fn deleted<O: Object<Project = P>>(&self) -> impl Iterator<Item = Ptr<O>> + '_ {
+        self.changes::<O>().filter(|(_, change)| *change == Change::Deleted).map(|(ptr, _)| ptr)
+    }
+
+}
+
+/// A handle to a change subscription, used to unsubscribe
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SubscriptionId(u64);
+
+enum SubscriptionTarget {
+    Project(Box<dyn FnMut()>),
+    Objects {
+        kind: &'static str,
+        /// If set, only changes to the object with this key are reported
+        key: Option<u64>,
+        callback: Box<dyn FnMut(u64, Change)>
+    }
+}
+
+/// The callbacks to call when the client changes
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    subscriptions: Vec<(SubscriptionId, SubscriptionTarget)>,
+    curr_id: u64
+}
+
+impl Subscriptions {
+
+    fn add(&mut self, target: SubscriptionTarget) -> SubscriptionId {
+        self.curr_id += 1;
+        let id = SubscriptionId(self.curr_id);
+        self.subscriptions.push((id, target));
+        id
+    }
+
+    pub(crate) fn add_project<F: FnMut() + 'static>(&mut self, callback: F) -> SubscriptionId {
+        self.add(SubscriptionTarget::Project(Box::new(callback)))
+    }
+
+    pub(crate) fn add_objects<F: FnMut(u64, Change) + 'static>(&mut self, kind: &'static str, key: Option<u64>, callback: F) -> SubscriptionId {
+        self.add(SubscriptionTarget::Objects {
+            kind,
+            key,
+            callback: Box::new(callback)
+        })
+    }
+
+    pub(crate) fn remove(&mut self, id: SubscriptionId) {
+        self.subscriptions.retain(|(subscription_id, _)| *subscription_id != id);
+    }
+
+    pub(crate) fn notify<P: Project>(&mut self, changes: &ChangeSet<P>) {
+        for (_, target) in &mut self.subscriptions {
+            match target {
+                SubscriptionTarget::Project(callback) => {
+                    if changes.project_modified {
+                        callback();
+                    }
+                },
+                SubscriptionTarget::Objects { kind, key, callback } => {
+                    let Some(kind_changes) = changes.objects.get(kind) else { continue; };
+                    match key {
+                        Some(key) => {
+                            if let Some(change) = kind_changes.change(*key) {
+                                callback(*key, change);
+                            }
+                        },
+                        None => {
+                            for (key, change) in kind_changes.iter() {
+                                callback(key, change);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+}
+
+impl<P: Project> Client<P> {
+
+    /// Call `callback` on every tick in which the project's own data was modified
+    pub fn subscribe_project<F: FnMut() + 'static>(&mut self, callback: F) -> SubscriptionId {
+        self.subscriptions.add_project(callback)
+    }
+
+    /// Call `callback` for every change to an object of kind `O`
+    pub fn subscribe_kind<O: Object<Project = P>, F: FnMut(Ptr<O>, Change) + 'static>(&mut self, mut callback: F) -> SubscriptionId {
+        self.subscriptions.add_objects(O::NAME, None, move |key, change| callback(Ptr::from_key(key), change))
+    }
+
+    /// Call `callback` whenever a specific object changes
+    pub fn subscribe_object<O: Object<Project = P>, F: FnMut(Change) + 'static>(&mut self, ptr: Ptr<O>, mut callback: F) -> SubscriptionId {
+        self.subscriptions.add_objects(O::NAME, Some(ptr.key), move |_, change| callback(change))
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) {
+        self.subscriptions.remove(id);
+    }
+
+}
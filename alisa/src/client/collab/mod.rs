@@ -1,11 +1,11 @@
 
-use std::cell::RefCell;
+use std::{cell::RefCell, collections::HashMap};
 
 use keychain::KeyChain;
 
-use crate::{rmpv_get, Delta, DeserializationContext, OperationDyn, Project, ProjectContextMut, Recorder, UnconfirmedOperation};
+use crate::{is_already_encoded, rmpv_get, Delta, DeserializationContext, Error, OperationDyn, Project, ProjectContextMut, Recorder, SerializationContext, UnconfirmedOperation};
 
-use super::{Client, ClientKind};
+use super::{Client, ClientKind, LoadTracker, Subscriptions};
 
 #[cfg(debug_assertions)]
 use super::verify_project_type;
@@ -16,7 +16,9 @@ pub(crate) struct Collab<P: Project> {
     keychain: RefCell<KeyChain<2>>,
     key_request_sent: bool,
     unconfirmed_operations: Vec<UnconfirmedOperation<P>>,
-    to_send: RefCell<Vec<rmpv::Value>>
+    to_send: RefCell<Vec<rmpv::Value>>,
+    /// The objects to request from the server, sent together in a single message
+    to_load: RefCell<Vec<(&'static str, u64)>>
 }
 
 impl<P: Project> Collab<P> {
@@ -26,7 +28,8 @@ impl<P: Project> Collab<P> {
             keychain: RefCell::new(KeyChain::new()),
             key_request_sent: false,
             unconfirmed_operations: Vec::new(),
-            to_send: RefCell::new(Vec::new())
+            to_send: RefCell::new(Vec::new()),
+            to_load: RefCell::new(Vec::new())
         }
     }
 
@@ -52,11 +55,11 @@ impl<P: Project> Collab<P> {
         }
     }
 
-    pub(crate) fn perform_operation(&mut self, operation: Box<dyn OperationDyn<Project = P>>, deltas: Vec<Box<dyn Delta<Project = P>>>) {
+    pub(crate) fn perform_operation(&mut self, operation: Box<dyn OperationDyn<Project = P>>, deltas: Vec<Box<dyn Delta<Project = P>>>, objects: &P::Objects) {
         self.send_message(rmpv::Value::Map(vec![
             ("type".into(), "operation".into()),
             ("operation".into(), operation.name().into()),
-            ("data".into(), operation.serialize())
+            ("data".into(), operation.serialize(objects))
         ]));
         self.unconfirmed_operations.push(UnconfirmedOperation {
             operation,
@@ -64,11 +67,40 @@ impl<P: Project> Collab<P> {
         });
     }
     
+    /// Are there operations the server hasn't confirmed yet?
+    pub(crate) fn has_unconfirmed_operations(&self) -> bool {
+        !self.unconfirmed_operations.is_empty()
+    }
+
     pub(crate) fn send_message(&self, message: rmpv::Value) {
         self.to_send.borrow_mut().push(message);
     }
 
+    /// Request an object from the server. Requests are batched into a single message when the messages are taken.
+    pub(crate) fn request_load(&self, object: &'static str, key: u64) {
+        self.to_load.borrow_mut().push((object, key));
+    }
+
     pub(crate) fn take_messages(&self) -> Vec<rmpv::Value> {
+        let to_load = std::mem::replace(&mut *self.to_load.borrow_mut(), Vec::new());
+        match to_load.as_slice() {
+            [] => {},
+            [(object, key)] => {
+                self.send_message(rmpv::Value::Map(vec![
+                    ("type".into(), "load".into()),
+                    ("object".into(), (*object).into()),
+                    ("key".into(), (*key).into()),
+                ]));
+            },
+            _ => {
+                self.send_message(rmpv::Value::Map(vec![
+                    ("type".into(), "load_many".into()),
+                    ("objects".into(), rmpv::Value::Array(
+                        to_load.iter().map(|(object, key)| rmpv::Value::Array(vec![(*object).into(), (*key).into()])).collect()
+                    )),
+                ]));
+            }
+        }
         std::mem::replace(&mut *self.to_send.borrow_mut(), Vec::new())
     }
 
@@ -76,21 +108,36 @@ impl<P: Project> Collab<P> {
 
 impl<P: Project> Client<P> {
 
-    pub fn collab(welcome_data: rmpv::Value) -> Option<Self> {
+    /// Create a collab client from the welcome data the server sent when it added the client. See `Server::add_client`.
+    pub fn collab(welcome_data: rmpv::Value) -> Result<Self, Error> {
 
         #[cfg(debug_assertions)]
         verify_project_type::<P>();
 
-        welcome_data.as_map()?;
-        let project_data = rmpv_get(&welcome_data, "project")?;
+        let Some(project_data) = welcome_data.as_map().and_then(|_| rmpv_get(&welcome_data, "project")) else {
+            return Err(Error::decode("expected project in welcome data"));
+        };
         let mut objects = P::Objects::default();
-        let project = P::deserialize(project_data, &mut DeserializationContext::collab(&mut objects))?;
-        Some(Self {
+        let (project, load_warnings) = {
+            let mut context = DeserializationContext::collab(&mut objects);
+            let project = P::deserialize(project_data, &mut context);
+            (project, context.take_errors())
+        };
+        let Some(project) = project else {
+            return Err(load_warnings.into_iter().next().map(Error::Decode).unwrap_or_else(|| Error::decode("invalid project data")));
+        };
+        Ok(Self {
             kind: ClientKind::Collab(Collab::new()),
             project,
             objects,
             operations_to_perform: RefCell::new(Vec::new()),
-            project_modified: false
+            project_modified: false,
+            load_warnings,
+            subscriptions: Subscriptions::default(),
+            memory_budget: None,
+            curr_tick: 0,
+            evicted_parents: RefCell::new(HashMap::new()),
+            loads: RefCell::new(LoadTracker::default())
         })
     }
 
@@ -98,7 +145,14 @@ impl<P: Project> Client<P> {
         // Find the type of operation being performed
         let operation_kind = P::OPERATIONS.iter().find(|kind| kind.name == operation_name)?;
         // Deserialize the operation from the message
-        let operation = (operation_kind.deserialize)(data)?; 
+        let (operation, mut operation_objects) = (operation_kind.deserialize)(data)?; 
+
+        // Rolling back and reapplying our operations touches objects that may end up unchanged, so keep their state to only report real changes
+        let project_data = self.project.serialize(&SerializationContext::shallow());
+        let project_modified = self.project_modified;
+        for object_kind in P::OBJECTS {
+            (object_kind.snapshot_changes)(&mut self.objects);
+        }
 
         let mut project_context = ProjectContextMut {
             project: &mut self.project,
@@ -118,6 +172,9 @@ impl<P: Project> Client<P> {
 
         // Apply the newly-received operation
         let mut recorder = Recorder::new(project_context);
+        for object_kind in P::OBJECTS {
+            (object_kind.instance_objects)(&mut operation_objects, &mut recorder);
+        }
         (operation_kind.perform)(operation, &mut recorder);
 
         // Reapply the operations we've done on top of the inserted operation
@@ -133,6 +190,13 @@ impl<P: Project> Client<P> {
             }
         }
 
+        for object_kind in P::OBJECTS {
+            (object_kind.forget_undone_changes)(&mut self.objects);
+        }
+        if !project_modified && self.project.serialize(&SerializationContext::shallow()) == project_data {
+            self.project_modified = false;
+        }
+
         Some(())
     }
 
@@ -149,7 +213,10 @@ impl<P: Project> Client<P> {
         let mut first = 0;
         let mut last = 0;
         let mut load_key = 0;
+        let mut load_depth = None;
         let mut object = "";
+        let mut objects: &[rmpv::Value] = &[];
+        let mut missing: &[rmpv::Value] = &[];
 
         for (key, val) in msg {
             let key = key.as_str()?;
@@ -175,6 +242,15 @@ impl<P: Project> Client<P> {
                 "object" => {
                     object = val.as_str()?;
                 },
+                "depth" => {
+                    load_depth = Some(val.as_u64()?.try_into().ok()?);
+                },
+                "objects" => {
+                    objects = val.as_array()?;
+                },
+                "missing" => {
+                    missing = val.as_array()?;
+                },
                 _ => {}
             }
         }
@@ -201,11 +277,47 @@ impl<P: Project> Client<P> {
             "load" => {
                 for object_kind in P::OBJECTS {
                     if object_kind.name == object {
-                        (object_kind.load_object_from_message)(&mut self.objects, load_key, data?);
+                        if let Some(data) = data {
+                            let warnings = (object_kind.load_object_from_message)(&mut self.objects, load_key, data, load_depth);
+                            self.load_warnings.extend(warnings);
+                        }
+                        // The object couldn't be deserialized. The problems are in the load warnings.
+                        if !(object_kind.contains)(&self.objects, load_key) {
+                            self.loads.borrow_mut().mark_failed(object, load_key);
+                        }
                         break;
                     }
                 }
             },
+            "load_failed" => {
+                self.loads.borrow_mut().mark_failed(object, load_key);
+            },
+            "load_many" => {
+                let mut received = Vec::new();
+                for loaded in objects {
+                    let Some([object, key, data]) = loaded.as_array().map(Vec::as_slice) else { continue; };
+                    let (Some(object), Some(key)) = (object.as_str(), key.as_u64()) else { continue; };
+                    let Some(object_kind) = P::OBJECTS.iter().find(|object_kind| object_kind.name == object) else { continue; };
+                    // Objects encoded elsewhere in the message are loaded along with the object containing them
+                    if !is_already_encoded(data) {
+                        let warnings = (object_kind.load_object_from_message)(&mut self.objects, key, data, None);
+                        self.load_warnings.extend(warnings);
+                    }
+                    received.push((object_kind, key));
+                }
+                // The objects that couldn't be deserialized, or whose containing object couldn't be, are missing. The problems are in the load warnings.
+                for (object_kind, key) in received {
+                    if !(object_kind.contains)(&self.objects, key) {
+                        self.loads.borrow_mut().mark_failed(object_kind.name, key);
+                    }
+                }
+                for not_found in missing {
+                    let Some([object, key]) = not_found.as_array().map(Vec::as_slice) else { continue; };
+                    if let (Some(object), Some(key)) = (object.as_str(), key.as_u64()) {
+                        self.loads.borrow_mut().mark_failed(object, key);
+                    }
+                }
+            },
             _ => {}
         }
 
@@ -213,3 +325,116 @@ impl<P: Project> Client<P> {
     }
    
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{already_encoded, test_project::{create_folder, Folder, SetFolderName, SetN, TestProject}, Action, Client, ClientId, LoadState, Object, Ptr, Server};
+
+    fn load_many_message(objects: Vec<(u64, rmpv::Value)>) -> rmpv::Value {
+        rmpv::Value::Map(vec![
+            ("type".into(), "load_many".into()),
+            ("objects".into(), rmpv::Value::Array(objects.into_iter().map(|(key, data)| rmpv::Value::Array(vec![Folder::NAME.into(), key.into(), data])).collect())),
+            ("missing".into(), rmpv::Value::Array(Vec::new()))
+        ])
+    }
+
+    fn folder_data(parent: u64, name: &str, folders: Vec<rmpv::Value>) -> rmpv::Value {
+        rmpv::Value::Map(vec![
+            ("parent".into(), parent.into()),
+            ("name".into(), name.into()),
+            ("folders".into(), rmpv::Value::Array(folders))
+        ])
+    }
+
+    fn collab_client() -> Client<TestProject> {
+        let mut server = Server::<TestProject>::in_memory(());
+        let (_, welcome) = server.add_client();
+        Client::collab(welcome).unwrap()
+    }
+
+    #[test]
+    fn objects_encoded_in_their_parent_are_loaded() {
+        let mut client = collab_client();
+        let parent = client.request_load(Ptr::<Folder>::from_key(5));
+        let child = client.request_load(Ptr::<Folder>::from_key(6));
+        let child_data = rmpv::Value::Array(vec![6.into(), folder_data(5, "Child", Vec::new())]);
+        client.receive_message(load_many_message(vec![
+            (5, folder_data(0, "Parent", vec![child_data])),
+            (6, already_encoded())
+        ]), &mut ()).unwrap();
+        client.tick(&mut ());
+        assert_eq!(parent.state(), LoadState::Loaded);
+        assert_eq!(child.state(), LoadState::Loaded);
+        assert_eq!(client.get(child.ptr()).unwrap().name, "Child");
+    }
+
+    #[test]
+    fn objects_missing_from_their_parent_are_not_found() {
+        let mut client = collab_client();
+        let parent = client.request_load(Ptr::<Folder>::from_key(5));
+        let child = client.request_load(Ptr::<Folder>::from_key(6));
+        // The parent's data is invalid, so the child encoded in it is lost
+        client.receive_message(load_many_message(vec![
+            (5, "not a folder".into()),
+            (6, already_encoded())
+        ]), &mut ()).unwrap();
+        client.tick(&mut ());
+        assert_eq!(parent.state(), LoadState::Loaded);
+        assert_eq!(child.state(), LoadState::NotFound);
+        assert!(!client.take_load_warnings().is_empty());
+    }
+
+    /// Send the client's messages to the server, then the server's replies back to the client
+    fn exchange_messages(server: &mut Server<TestProject>, client_id: ClientId, client: &mut Client<TestProject>) {
+        for msg in client.take_messages() {
+            server.receive_message(client_id, msg).unwrap();
+        }
+        for msg in std::mem::take(server.get_msgs_to_send(client_id).unwrap()) {
+            client.receive_message(msg, &mut ()).unwrap();
+        }
+    }
+
+    #[test]
+    fn rolling_back_only_reports_real_changes() {
+        let mut server = Server::<TestProject>::in_memory(());
+        let (client_id, welcome) = server.add_client();
+        let mut client = Client::<TestProject>::collab(welcome).unwrap();
+        let (other_id, welcome) = server.add_client();
+        let mut other = Client::<TestProject>::collab(welcome).unwrap();
+
+        client.tick(&mut ());
+        exchange_messages(&mut server, client_id, &mut client);
+        let folder = create_folder(&client, Ptr::null(), "Folder");
+        client.tick(&mut ());
+        exchange_messages(&mut server, client_id, &mut client);
+
+        // The rename is rolled back and reapplied when the other client's operation arrives, leaving the folder as it was
+        client.perform(&mut Action::new(), SetFolderName { ptr: folder, name_value: "Renamed".to_owned() });
+        client.tick(&mut ());
+        other.perform(&mut Action::new(), SetN { n: 2 });
+        other.tick(&mut ());
+        exchange_messages(&mut server, other_id, &mut other);
+        for msg in std::mem::take(server.get_msgs_to_send(client_id).unwrap()) {
+            client.receive_message(msg, &mut ()).unwrap();
+        }
+        let changes = client.tick(&mut ());
+        assert!(changes.project_modified());
+        assert_eq!(changes.change(folder), None);
+        assert_eq!(client.get(folder).unwrap().name, "Renamed");
+
+        // Same for the project
+        client.perform(&mut Action::new(), SetN { n: 5 });
+        client.tick(&mut ());
+        other.perform(&mut Action::new(), SetN { n: 7 });
+        other.tick(&mut ());
+        exchange_messages(&mut server, other_id, &mut other);
+        for msg in std::mem::take(server.get_msgs_to_send(client_id).unwrap()) {
+            client.receive_message(msg, &mut ()).unwrap();
+        }
+        let changes = client.tick(&mut ());
+        assert!(!changes.project_modified());
+        assert!(changes.is_empty());
+        assert_eq!(client.project().n, 5);
+    }
+
+}
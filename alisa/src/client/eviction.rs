@@ -0,0 +1,173 @@
+
+use std::collections::{HashMap, HashSet};
+
+use crate::Project;
+
+use super::{Client, ClientKind};
+
+impl<P: Project> Client<P> {
+
+    /// Limit the estimated memory used by loaded objects, in bytes. See `Object::memory_size`.
+    /// When the budget is exceeded, the least recently used objects are unloaded on tick, after saving their modifications.
+    /// Evicted objects are requested again when accessed through `Client::get`. Objects accessed since the last tick are never evicted,
+    /// and neither are objects whose children are loaded, or any objects while operations are waiting to be confirmed by the server.
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.memory_budget = budget;
+    }
+
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.memory_budget
+    }
+
+    /// The estimated memory used by all the loaded objects, in bytes
+    pub fn memory_usage(&self) -> usize {
+        P::OBJECTS.iter()
+            .flat_map(|object_kind| (object_kind.memory_usage)(&self.objects, self.curr_tick))
+            .map(|object| object.size)
+            .sum()
+    }
+
+    /// Unload the objects requested using `Client::unload`, and evict objects if the memory budget is exceeded
+    pub(crate) fn unload_objects(&mut self) {
+        let mut unloaded = Vec::new();
+        for object_kind in P::OBJECTS {
+            for key in (object_kind.unload_requested)(&mut self.objects) {
+                unloaded.push((object_kind.name, key));
+            }
+        }
+
+        for object_kind in P::OBJECTS {
+            (object_kind.record_accesses)(&self.objects, self.curr_tick);
+        }
+
+        // Undoing and redoing the operations the server hasn't confirmed yet needs the objects they changed
+        let pending_operations = self.kind.as_collab().is_some_and(|collab| collab.has_unconfirmed_operations());
+
+        if let Some(budget) = self.memory_budget.filter(|_| !pending_operations) {
+            let mut usage = Vec::new();
+            let mut total_size = 0;
+            for object_kind in P::OBJECTS {
+                for object in (object_kind.memory_usage)(&self.objects, self.curr_tick) {
+                    total_size += object.size;
+                    usage.push((object_kind, object));
+                }
+            }
+
+            // Operations on an object can change its parent, like removing the object from the parent's child list, so parents are kept while their children are loaded
+            let mut references = HashMap::new();
+            if total_size > budget {
+                for (object_kind, object) in &usage {
+                    references.insert(object.key, (object_kind.references)(&self.objects, object.key));
+                }
+            }
+            let loaded = usage.iter().map(|(_, object)| object.key).collect::<HashSet<_>>();
+            let parents = references.iter()
+                .filter(|(_, children)| children.iter().any(|child| loaded.contains(child)))
+                .map(|(key, _)| *key)
+                .collect::<HashSet<_>>();
+
+            usage.sort_by_key(|(_, object)| object.last_access);
+            for (object_kind, object) in usage {
+                if total_size <= budget || object.last_access >= self.curr_tick {
+                    break;
+                }
+                if parents.contains(&object.key) {
+                    continue;
+                }
+                if (object_kind.evict)(&mut self.objects, object.key) {
+                    total_size -= object.size;
+                    unloaded.push((object_kind.name, object.key));
+                    for child in references.remove(&object.key).unwrap_or_default() {
+                        self.evicted_parents.get_mut().insert(child, (object_kind, object.key));
+                    }
+                }
+            }
+        }
+
+        // Let the server know we no longer hold the objects
+        if let Some(collab) = self.kind.as_collab() {
+            for (object, key) in unloaded {
+                collab.send_message(rmpv::Value::Map(vec![
+                    ("type".into(), "unload".into()),
+                    ("object".into(), object.into()),
+                    ("key".into(), key.into()),
+                ]));
+            }
+        }
+    }
+
+    /// Reload the parents evicted after an evicted object that is being reloaded, so operations on the object can update them
+    pub(crate) fn reload_evicted_parents(&self, mut key: u64) {
+        loop {
+            let Some((object_kind, parent)) = self.evicted_parents.borrow_mut().remove(&key) else { break; };
+            if (object_kind.unevict)(&self.objects, parent) {
+                match &self.kind {
+                    ClientKind::Local(_) => (object_kind.queue_load)(&self.objects, parent),
+                    ClientKind::Collab(collab) => collab.request_load(object_kind.name, parent),
+                }
+            }
+            key = parent;
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_project::{create_folder, DeleteFolder, SetFolderName, TestProject}, Action, Client, Ptr};
+
+    #[test]
+    fn edits_survive_eviction() {
+        let mut client = Client::<TestProject>::in_memory();
+        let folders = (0..3).map(|i| create_folder(&client, Ptr::null(), &format!("Folder {}", i))).collect::<Vec<_>>();
+        client.tick(&mut ());
+        client.set_memory_budget(Some(0));
+
+        // Objects edited in a tick are kept through that tick
+        client.perform(&mut Action::new(), SetFolderName { ptr: folders[0], name_value: "Edited".to_owned() });
+        client.tick(&mut ());
+        assert_eq!(client.objects.folders.get_untracked(folders[0]).unwrap().name, "Edited");
+
+        client.tick(&mut ());
+        assert!(client.objects.folders.is_empty());
+
+        // Evicted objects are reloaded with the edit
+        assert!(client.get(folders[0]).is_none());
+        client.tick(&mut ());
+        assert_eq!(client.get(folders[0]).unwrap().name, "Edited");
+    }
+
+    #[test]
+    fn parents_are_kept_while_children_are_loaded() {
+        let mut client = Client::<TestProject>::in_memory();
+        let parent = create_folder(&client, Ptr::null(), "Parent");
+        let child = create_folder(&client, parent, "Child");
+        client.tick(&mut ());
+        client.set_memory_budget(Some(0));
+        client.tick(&mut ());
+
+        client.tick(&mut ());
+        assert!(client.objects.folders.contains(parent));
+        assert!(!client.objects.folders.contains(child));
+        client.tick(&mut ());
+        assert!(client.objects.folders.is_empty());
+
+        // Reloading the child reloads its parent, so deleting the child removes it from the parent
+        assert!(client.get(child).is_none());
+        client.tick(&mut ());
+        assert!(client.objects.folders.contains(parent));
+        client.perform(&mut Action::new(), DeleteFolder { ptr: child });
+        client.tick(&mut ());
+        assert_eq!(client.objects.folders.get_untracked(parent).unwrap().folders.iter().count(), 0);
+
+        client.set_memory_budget(None);
+        client.tick(&mut ());
+        client.unload(parent);
+        client.tick(&mut ());
+        client.request_load(parent);
+        client.tick(&mut ());
+        assert_eq!(client.get(parent).unwrap().folders.iter().count(), 0);
+        assert!(client.get(child).is_none());
+    }
+
+}
@@ -0,0 +1,216 @@
+
+use std::{cell::Cell, collections::{HashMap, HashSet}, rc::Rc};
+
+use crate::{Object, Project, Ptr, TreeObj};
+
+use super::{Client, ClientKind};
+
+/// The loading state of an object
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum LoadState {
+    /// The object is not in memory, and hasn't been requested
+    NotLoaded,
+    /// The object was requested, but hasn't arrived yet
+    Loading,
+    Loaded,
+    /// The object could not be found in the project file or on the server
+    NotFound
+}
+
+/// A handle to a requested object load that can be polled to see when the object arrives.
+/// Returned by `Client::request_load`.
+pub struct LoadHandle<O: Object> {
+    ptr: Ptr<O>,
+    state: Rc<Cell<LoadState>>
+}
+
+impl<O: Object> LoadHandle<O> {
+
+    pub fn ptr(&self) -> Ptr<O> {
+        self.ptr
+    }
+
+    /// The state of the load. Updated when the client ticks.
+    pub fn state(&self) -> LoadState {
+        self.state.get()
+    }
+
+    /// Did the load finish, either successfully or not?
+    pub fn is_done(&self) -> bool {
+        self.state() != LoadState::Loading
+    }
+
+}
+
+struct PendingLoad {
+    /// Did the server tell us it doesn't have the object?
+    failed: bool,
+    handles: Vec<Rc<Cell<LoadState>>>,
+    callbacks: Vec<Box<dyn FnOnce(LoadState)>>
+}
+
+/// The loads requested by the user, identified by the object kind's name and the object's key
+#[derive(Default)]
+pub(crate) struct LoadTracker {
+    pending: HashMap<(&'static str, u64), PendingLoad>,
+    not_found: HashSet<(&'static str, u64)>
+}
+
+impl LoadTracker {
+
+    /// Start tracking a load. Returns false if the load was already pending.
+    fn add(&mut self, object: &'static str, key: u64, state: Rc<Cell<LoadState>>) -> bool {
+        self.not_found.remove(&(object, key));
+        match self.pending.get_mut(&(object, key)) {
+            Some(pending) => {
+                pending.handles.push(state);
+                false
+            },
+            None => {
+                self.pending.insert((object, key), PendingLoad {
+                    failed: false,
+                    handles: vec![state],
+                    callbacks: Vec::new()
+                });
+                true
+            }
+        }
+    }
+
+    pub(crate) fn mark_failed(&mut self, object: &str, key: u64) {
+        for ((pending_object, pending_key), pending) in self.pending.iter_mut() {
+            if *pending_object == object && *pending_key == key {
+                pending.failed = true;
+            }
+        }
+    }
+
+}
+
+impl<P: Project> Client<P> {
+
+    /// Request an object to be loaded from the project file or the server.
+    /// The returned handle can be polled to see when the object arrives, or if it could not be found.
+    pub fn request_load<O: Object<Project = P>>(&self, ptr: Ptr<O>) -> LoadHandle<O> {
+        self.request_load_with_depth(ptr, None)
+    }
+
+    /// Request a tree object to be loaded along with its descendants up to `depth` levels below it, in a single request.
+    /// A depth of 0 loads just the object. Descendants that are already loaded are walked too, so that their missing children get loaded.
+    pub fn request_load_subtree<O: TreeObj<Project = P>>(&self, ptr: Ptr<O>, depth: u32) -> LoadHandle<O> {
+        self.request_load_with_depth(ptr, Some(depth))
+    }
+
+    fn request_load_with_depth<O: Object<Project = P>>(&self, ptr: Ptr<O>, subtree_depth: Option<u32>) -> LoadHandle<O> {
+        if subtree_depth.is_none() && O::list(&self.objects).contains(ptr) {
+            return LoadHandle {
+                ptr,
+                state: Rc::new(Cell::new(LoadState::Loaded))
+            };
+        }
+
+        let state = Rc::new(Cell::new(LoadState::Loading));
+        if !self.loads.borrow_mut().add(O::NAME, ptr.key, state.clone()) {
+            // Already requested, so just wait for the existing request
+            return LoadHandle { ptr, state };
+        }
+
+        match &self.kind {
+            ClientKind::Local(_) => { O::list(&self.objects).to_load.borrow_mut().insert(ptr, subtree_depth); },
+            ClientKind::Collab(collab) => {
+                match subtree_depth {
+                    Some(depth) => {
+                        collab.send_message(rmpv::Value::Map(vec![
+                            ("type".into(), "load".into()),
+                            ("object".into(), O::NAME.into()),
+                            ("key".into(), ptr.key.into()),
+                            ("depth".into(), depth.into()),
+                        ]));
+                    },
+                    None => collab.request_load(O::NAME, ptr.key),
+                }
+            },
+        }
+
+        LoadHandle { ptr, state }
+    }
+
+    /// Request an object to be loaded, calling `callback` on tick once it arrives or can't be found.
+    /// If the object is already loaded, the callback is called immediately.
+    pub fn request_load_then<O: Object<Project = P>, F: FnOnce(LoadState) + 'static>(&self, ptr: Ptr<O>, callback: F) {
+        let handle = self.request_load(ptr);
+        if handle.is_done() {
+            callback(handle.state());
+            return;
+        }
+        if let Some(pending) = self.loads.borrow_mut().pending.get_mut(&(O::NAME, ptr.key)) {
+            pending.callbacks.push(Box::new(callback));
+        }
+    }
+
+    pub fn load_state<O: Object<Project = P>>(&self, ptr: Ptr<O>) -> LoadState {
+        if O::list(&self.objects).contains(ptr) {
+            return LoadState::Loaded;
+        }
+        let loads = self.loads.borrow();
+        if loads.pending.contains_key(&(O::NAME, ptr.key)) {
+            LoadState::Loading
+        } else if loads.not_found.contains(&(O::NAME, ptr.key)) {
+            LoadState::NotFound
+        } else {
+            LoadState::NotLoaded
+        }
+    }
+
+    pub fn is_loaded<O: Object<Project = P>>(&self, ptr: Ptr<O>) -> bool {
+        self.load_state(ptr) == LoadState::Loaded
+    }
+
+    pub fn is_loading<O: Object<Project = P>>(&self, ptr: Ptr<O>) -> bool {
+        self.load_state(ptr) == LoadState::Loading
+    }
+
+    /// The objects of a kind that were requested but haven't arrived yet
+    pub fn pending_loads<O: Object<Project = P>>(&self) -> Vec<Ptr<O>> {
+        self.loads.borrow().pending.keys()
+            .filter(|(object, _)| *object == O::NAME)
+            .map(|(_, key)| Ptr::from_key(*key))
+            .collect()
+    }
+
+    /// Finish the loads of the objects that arrived or could not be found, updating their handles and calling their callbacks
+    pub(crate) fn resolve_loads(&mut self) {
+        let is_local = self.is_local();
+        let finished = {
+            let mut loads = self.loads.borrow_mut();
+            let mut finished = Vec::new();
+            let keys = loads.pending.keys().copied().collect::<Vec<_>>();
+            for (object, key) in keys {
+                let Some(object_kind) = P::OBJECTS.iter().find(|object_kind| object_kind.name == object) else { continue; };
+                let state = if (object_kind.contains)(&self.objects, key) {
+                    LoadState::Loaded
+                } else if loads.pending[&(object, key)].failed || is_local {
+                    // Local loads happen during the tick, so if the object still isn't there, it isn't in the file
+                    loads.not_found.insert((object, key));
+                    LoadState::NotFound
+                } else {
+                    continue;
+                };
+                if let Some(pending) = loads.pending.remove(&(object, key)) {
+                    finished.push((state, pending));
+                }
+            }
+            finished
+        };
+
+        for (state, pending) in finished {
+            for handle in pending.handles {
+                handle.set(state);
+            }
+            for callback in pending.callbacks {
+                callback(state);
+            }
+        }
+    }
+
+}
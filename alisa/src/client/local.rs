@@ -1,20 +1,25 @@
 
-use std::{cell::RefCell, marker::PhantomData, path::Path};
+use std::{cell::RefCell, collections::HashMap, marker::PhantomData, path::{Path, PathBuf}};
 
-use crate::{File, ObjectKind, Project, SerializationContext};
+use crate::{compact_into, not_in_file_error, CompactionReport, DeserializationError, Error, File, GarbageReport, MemoryStorage, ObjectKind, Project, SerializationContext, Storage};
 
-use super::{Client, ClientKind};
+use super::{Client, ClientKind, LoadTracker, Subscriptions};
 
 #[cfg(debug_assertions)]
 use super::verify_project_type;
 
 pub(crate) struct Local<P: Project> {
-    /// The Verter file to which the project is saved
+    /// The storage to which the project is saved
     file: File,
     /// The next key available for use
     curr_key: RefCell<u64>,
-    /// Does the root data of the Verter file need to be updated?
+    /// Does the next available key need to be saved?
     root_data_modified: RefCell<bool>,
+    /// Does the project's own data need to be saved? Kept apart from the client's flag, which is reset as soon as the change is reported.
+    project_unsaved: bool,
+    /// Was the file closed to be replaced by a compacted copy, and then could not be reopened?
+    /// If so, `file` is an empty placeholder, so nothing can be saved until the project is moved with `save_as`.
+    file_lost: bool,
 
     /// Marker to make sure the type `P`` is used
     _marker: PhantomData<P>
@@ -27,6 +32,8 @@ impl<P: Project> Local<P> {
             file,
             curr_key: RefCell::new(curr_key),
             root_data_modified: RefCell::new(false),
+            project_unsaved: false,
+            file_lost: false,
             _marker: PhantomData
         }
     }
@@ -47,60 +54,402 @@ impl<P: Project> Local<P> {
         (first, first + n_keys - 1)
     }
 
-    fn update_root_data(&mut self) {
-        self.file.update_root(*self.curr_key.borrow()); 
+    fn update_root_data(&mut self) -> Result<(), Error> {
+        self.file.set_curr_key(*self.curr_key.borrow())
     }
 
-    pub(crate) fn save_changes(&mut self, project: &mut P, objects: &mut P::Objects, project_modified: &mut bool) {
+    /// Save all the pending changes to the file, returning the first problem encountered.
+    /// The changes are only committed if all of them could be saved. Otherwise, they are kept and saving them is retried on the next save.
+    pub(crate) fn save_changes(&mut self, project: &mut P, objects: &mut P::Objects, project_modified: bool) -> Result<(), Error> {
+        if self.file_lost {
+            return Err(Error::io(std::io::ErrorKind::NotFound, "the project file could not be reopened after compacting it, use `Client::save_as` to save the project to a new file"));
+        }
+
+        let mut result = Ok(());
 
         // Update file root data if necessary 
         if *self.root_data_modified.borrow() {
-            self.update_root_data();
-            *self.root_data_modified.borrow_mut() = false;
+            let root_result = self.update_root_data();
+            *self.root_data_modified.borrow_mut() = root_result.is_err();
+            result = result.and(root_result);
         }
 
         // Project modifications
-        if *project_modified {
+        self.project_unsaved |= project_modified;
+        if self.project_unsaved {
             let data = project.serialize(&SerializationContext::shallow());
-            self.file.write_project(&data);
-            *project_modified = false;
+            let project_result = self.file.write_project(&data);
+            self.project_unsaved = project_result.is_err();
+            result = result.and(project_result);
         }
 
         // Object modifications
         for object_kind in P::OBJECTS {
-            (object_kind.save_modifications)(&mut self.file, objects);
+            result = result.and((object_kind.save_modifications)(&mut self.file, objects));
+        }
+
+        // Save the tick's changes all at once, so a crash never leaves the file half-saved
+        result.and_then(|_| self.file.commit())
+    }
+
+    /// Save all pending changes, then delete the objects in the file that can't be reached from the project.
+    /// Deleted objects that are loaded are removed from memory too, and reported as deleted on the next tick.
+    pub(crate) fn collect_garbage(&mut self, project: &mut P, objects: &mut P::Objects, project_modified: bool) -> Result<GarbageReport, Error> {
+        self.save_changes(project, objects, project_modified)?;
+        let report = self.file.collect_garbage::<P>()?;
+        for key in &report.removed {
+            for object_kind in P::OBJECTS {
+                (object_kind.forget)(objects, *key);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Save all pending changes, then replace the file with a compacted copy of itself
+    pub(crate) fn compact(&mut self, project: &mut P, objects: &mut P::Objects, project_modified: bool) -> Result<CompactionReport, Error> {
+        self.save_changes(project, objects, project_modified)?;
+
+        // Write the compacted copy next to the file, then move it into place
+        let path = self.file.path().ok_or_else(not_in_file_error)?.to_owned();
+        let mut compacted_path = path.clone().into_os_string();
+        compacted_path.push(".compacting");
+        let compacted_path = PathBuf::from(compacted_path);
+        // Left over from a compaction that was interrupted
+        if compacted_path.exists() {
+            std::fs::remove_file(&compacted_path)?;
         }
 
+        let report = compact_into::<P>(&mut self.file, *self.curr_key.borrow(), &compacted_path).and_then(|report| {
+            // Make sure the compacted file can be opened before it replaces the original
+            File::open::<P, _>(&compacted_path, false)?;
+            Ok(report)
+        });
+        let report = match report {
+            Ok(report) => report,
+            Err(error) => {
+                let _ = std::fs::remove_file(&compacted_path);
+                return Err(error);
+            }
+        };
+
+        // Some platforms, like Windows, can't replace a file that is still open
+        drop(std::mem::replace(&mut self.file, File::from_storage(Box::new(MemoryStorage::new()))));
+        let renamed = std::fs::rename(&compacted_path, &path);
+
+        match File::open::<P, _>(&path, false) {
+            Ok((file, _, _, _, _)) => {
+                self.file = file;
+                match renamed {
+                    Ok(()) => Ok(report),
+                    Err(error) => {
+                        // The original file is still in place, so nothing was lost
+                        let _ = std::fs::remove_file(&compacted_path);
+                        Err(error.into())
+                    }
+                }
+            },
+            Err(error) => {
+                // Everything is still in memory, and the project is still on disk, either at `path` or, if it couldn't be replaced, next to it in the compacted copy.
+                // There is no open file to save to, though, so saving fails until the project is moved with `save_as`.
+                self.file_lost = true;
+                Err(error)
+            }
+        }
     }
 
-    pub(crate) fn load_objects(&mut self, objects: &mut P::Objects) {
+    /// Write the project into a new file, including the objects that aren't loaded and the changes that haven't been saved yet
+    fn write_copy(&mut self, path: &Path, project: &P, objects: &P::Objects) -> Result<File, Error> {
+        if path.exists() {
+            return Err(Error::io(std::io::ErrorKind::AlreadyExists, "file already exists"));
+        }
+        let (mut copy, _, _, _, _) = File::open::<P, _>(path, false)?;
+        let result = self.file.copy_into::<P>(&mut copy, *self.curr_key.borrow()).and_then(|_| {
+            copy.write_project(&project.serialize(&SerializationContext::shallow()))?;
+            for object_kind in P::OBJECTS {
+                (object_kind.write_modifications)(&mut copy, objects)?;
+            }
+            copy.commit()
+        });
+        if let Err(error) = result {
+            drop(copy);
+            let _ = std::fs::remove_file(path);
+            return Err(error);
+        }
+        Ok(copy)
+    }
+
+    pub(crate) fn save_as(&mut self, path: &Path, project: &P, objects: &P::Objects) -> Result<(), Error> {
+        // The pending changes are saved to the new file on the next tick, just like they would have been to the old one
+        self.file = self.write_copy(path, project, objects)?;
+        self.file_lost = false;
+        Ok(())
+    }
+
+    pub(crate) fn save_copy(&mut self, path: &Path, project: &P, objects: &P::Objects) -> Result<(), Error> {
+        self.write_copy(path, project, objects)?;
+        Ok(())
+    }
+
+    pub(crate) fn load_objects(&mut self, objects: &mut P::Objects) -> Vec<DeserializationError> {
+        let mut errors = Vec::new();
         for object_kind in P::OBJECTS {
-            (object_kind.load_objects)(&mut self.file, objects)
+            errors.extend((object_kind.load_objects)(&mut self.file, objects));
         }
+        errors
     }
 
-    pub(crate) fn dyn_load(&mut self, obj_kind: &ObjectKind<P>, objects: &mut P::Objects, key: u64) {
-        (obj_kind.load_object)(&mut self.file, objects, key);
+    pub(crate) fn dyn_load(&mut self, obj_kind: &ObjectKind<P>, objects: &mut P::Objects, key: u64, subtree_depth: Option<u32>) -> Vec<DeserializationError> {
+        (obj_kind.load_object)(&mut self.file, objects, key, subtree_depth)
     }
 
 }
 
 impl<P: Project> Client<P> {
 
-    pub fn local<PathRef: AsRef<Path>>(path: PathRef) -> Option<Self> {
+    fn local_from_file(file: File, project: P, objects: P::Objects, curr_key: u64, load_warnings: Vec<DeserializationError>) -> Self {
 
         #[cfg(debug_assertions)]
         verify_project_type::<P>();
-        
-        let (file, project, objects, curr_key) = File::open(path)?;
 
-        Some(Self {
+        Self {
             kind: ClientKind::Local(Local::new(file, curr_key)),
             project,
             objects,
             operations_to_perform: RefCell::new(Vec::new()),
-            project_modified: false
-        })
+            project_modified: false,
+            load_warnings,
+            subscriptions: Subscriptions::default(),
+            memory_budget: None,
+            curr_tick: 0,
+            evicted_parents: RefCell::new(HashMap::new()),
+            loads: RefCell::new(LoadTracker::default())
+        }
+    }
+
+    fn open_local<PathRef: AsRef<Path>>(path: PathRef, strict: bool) -> Result<Self, Error> {
+        let (file, project, objects, curr_key, load_warnings) = File::open(path, strict)?;
+        Ok(Self::local_from_file(file, project, objects, curr_key, load_warnings))
+    }
+
+    /// Create a new, empty project kept in memory instead of in a file. It works just like a project opened with `Client::local`,
+    /// except nothing is written to the disk. Use `Client::save_as` to move it into a file.
+    pub fn in_memory() -> Self {
+        // Reading and writing memory can't fail
+        Self::with_storage(MemoryStorage::new()).expect("could not create in-memory project")
+    }
+
+    /// Open a project kept in a custom storage. Works just like `Client::local`, but reads and writes the project through `storage`.
+    pub fn with_storage<S: Storage + 'static>(storage: S) -> Result<Self, Error> {
+        let (file, project, objects, curr_key, load_warnings) = File::open_storage(Box::new(storage), false)?;
+        Ok(Self::local_from_file(file, project, objects, curr_key, load_warnings))
+    }
+
+    /// Open a project file, or a project directory written by `DirectoryStorage`. Problems with the data in the file are skipped over and can be retrieved using `Client::take_load_warnings`.
+    /// Fails if the project data can't be read at all, leaving the file untouched. See `check_file` and `repair_file`.
+    /// Files from an older version of the project format are opened strictly, since their migrated data is written back.
+    pub fn local<PathRef: AsRef<Path>>(path: PathRef) -> Result<Self, Error> {
+        Self::open_local(path, false)
+    }
+
+    /// Delete all the objects in the project file that can't be reached from the project, following both `LoadingPtr`s and plain `Ptr`s.
+    /// Pending changes are saved first, and the deleted objects that are loaded are removed from memory and reported as deleted by the next tick.
+    /// Nothing is deleted if any of the reachable data can't be read, except for pointers to objects missing from the file, which are reported in `GarbageReport::dangling`.
+    pub fn collect_garbage(&mut self) -> Result<GarbageReport, Error> {
+        let Some(local) = self.kind.as_local() else {
+            return Err(Error::io(std::io::ErrorKind::Unsupported, "garbage can only be collected from a local project file"));
+        };
+        local.collect_garbage(&mut self.project, &mut self.objects, self.project_modified)
+    }
+
+    /// Compact the project file, freeing the space left behind by deleted objects and leaving out objects that can't be reached from the project. Pending changes are saved first.
+    /// The compacted file is written next to the project file and then moved over it, so the project file is never left half-written.
+    /// If the project file can't be reopened afterwards, saving fails until the project is moved to a new file with `Client::save_as`.
+    /// If the compacted file couldn't be moved into place either, it is kept next to the project file with a `.compacting` extension.
+    pub fn compact(&mut self) -> Result<CompactionReport, Error> {
+        let Some(local) = self.kind.as_local() else {
+            return Err(Error::io(std::io::ErrorKind::Unsupported, "only local project files can be compacted"));
+        };
+        local.compact(&mut self.project, &mut self.objects, self.project_modified)
+    }
+
+    /// Save the project into a new file and keep working on the new file from now on.
+    /// Everything in the project is copied, including the objects that aren't loaded and the changes made since the last tick, but objects that can't be reached from the project are left out.
+    /// Fails if the file already exists.
+    pub fn save_as<PathRef: AsRef<Path>>(&mut self, path: PathRef) -> Result<(), Error> {
+        let Some(local) = self.kind.as_local() else {
+            return Err(Error::io(std::io::ErrorKind::Unsupported, "only local projects can be saved to a file"));
+        };
+        local.save_as(path.as_ref(), &self.project, &self.objects)
+    }
+
+    /// Save a copy of the project into a new file, and keep working on the original file.
+    /// Everything in the project is copied, including the objects that aren't loaded and the changes made since the last tick, but objects that can't be reached from the project are left out.
+    /// Fails if the file already exists.
+    pub fn save_copy<PathRef: AsRef<Path>>(&mut self, path: PathRef) -> Result<(), Error> {
+        let Some(local) = self.kind.as_local() else {
+            return Err(Error::io(std::io::ErrorKind::Unsupported, "only local projects can be saved to a file"));
+        };
+        local.save_copy(path.as_ref(), &self.project, &self.objects)
+    }
+
+    /// Open a project file, failing if any of the data in the file can't be deserialized.
+    pub fn local_strict<PathRef: AsRef<Path>>(path: PathRef) -> Result<Self, Error> {
+        Self::open_local(path, true)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_project::{create_folder, temp_path, DeleteFolder, Folder, SetFolderName, SetN, TestProject}, Action, Change, Children, Client, DirectoryFormat, DirectoryStorage, File, Object, Ptr, Serializable, SerializationContext, UnorderedChildList};
+
+    #[test]
+    fn compacting_frees_deleted_objects() {
+        let path = temp_path("compact.alisa");
+        let mut client = Client::<TestProject>::local(&path).unwrap();
+        let folders = (0..50).map(|i| create_folder(&client, Ptr::null(), &format!("Folder {} {}", i, "x".repeat(200)))).collect::<Vec<_>>();
+        client.tick(&mut ());
+        for folder in &folders[1..] {
+            client.perform(&mut Action::new(), DeleteFolder { ptr: *folder });
+        }
+        client.tick(&mut ());
+
+        let report = client.compact().unwrap();
+        assert!(report.compacted_size < report.original_size);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), report.compacted_size);
+        let mut compacted_path = path.clone().into_os_string();
+        compacted_path.push(".compacting");
+        assert!(!std::path::Path::new(&compacted_path).exists());
+
+        // Changes made after compacting are saved to the compacted file
+        let added = create_folder(&client, Ptr::null(), "Added");
+        client.tick(&mut ());
+        drop(client);
+
+        let mut client = Client::<TestProject>::local(&path).unwrap();
+        assert_eq!(client.project().folders.iter().count(), 2);
+        client.request_load(folders[0]);
+        client.request_load(added);
+        client.tick(&mut ());
+        assert!(client.get(folders[0]).unwrap().name.starts_with("Folder 0 "));
+        assert_eq!(client.get(added).unwrap().name, "Added");
+        assert!(client.get(folders[1]).is_none());
+
+        drop(client);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn collecting_garbage_removes_unreachable_objects() {
+        let mut client = Client::<TestProject>::in_memory();
+        let kept = create_folder(&client, Ptr::null(), "Kept");
+        let child = create_folder(&client, kept, "Child");
+        client.tick(&mut ());
+
+        // Point the kept folder to a folder missing from the file, and add a folder nothing points to
+        let missing = client.next_ptr::<Folder>().unwrap();
+        let garbage = client.next_ptr::<Folder>().unwrap();
+        let mut folders = UnorderedChildList::new();
+        folders.insert((), child);
+        folders.insert((), missing);
+        let kept_data = Folder { parent: Ptr::null(), name: "Kept".to_owned(), folders }.serialize(&SerializationContext::shallow());
+        let garbage_data = Folder { parent: Ptr::null(), name: "Garbage".to_owned(), folders: UnorderedChildList::new() }.serialize(&SerializationContext::shallow());
+        let file = &mut client.kind.as_local().unwrap().file;
+        file.write(Folder::NAME, kept.key, &kept_data).unwrap();
+        file.write(Folder::NAME, garbage.key, &garbage_data).unwrap();
+        file.commit().unwrap();
+        client.request_load(garbage);
+        client.tick(&mut ());
+        assert!(client.objects.folders.contains(garbage));
+
+        let report = client.collect_garbage().unwrap();
+        assert_eq!(report.removed, vec![garbage.key]);
+        assert_eq!(report.kept, 2);
+        assert_eq!(report.dangling.len(), 1);
+        assert_eq!(report.dangling[0].unavailable_object(), Some((Folder::NAME, missing.key)));
+
+        // The deleted folder is dropped from memory too
+        assert!(!client.objects.folders.contains(garbage));
+        assert_eq!(client.tick(&mut ()).change(garbage), Some(Change::Deleted));
+        assert!(client.get(kept).is_some());
+        assert!(client.get(child).is_some());
+        client.request_load(garbage);
+        client.tick(&mut ());
+        assert!(client.get(garbage).is_none());
+    }
+
+    #[test]
+    fn save_as_copies_the_project_into_a_new_file() {
+        let path = temp_path("save_as_original.alisa");
+        let copy_path = temp_path("save_as_copy.alisa");
+        let mut client = Client::<TestProject>::local(&path).unwrap();
+        let parent = create_folder(&client, Ptr::null(), "Parent");
+        let child = create_folder(&client, parent, "Child");
+        client.tick(&mut ());
+
+        // A folder in the file that nothing points to
+        let garbage = client.next_ptr::<Folder>().unwrap();
+        let garbage_data = Folder { parent: Ptr::null(), name: "Garbage".to_owned(), folders: UnorderedChildList::new() }.serialize(&SerializationContext::shallow());
+        let file = &mut client.kind.as_local().unwrap().file;
+        file.write(Folder::NAME, garbage.key, &garbage_data).unwrap();
+        file.commit().unwrap();
+
+        client.unload(child);
+        client.perform(&mut Action::new(), SetFolderName { ptr: parent, name_value: "Renamed".to_owned() });
+        client.tick(&mut ());
+        client.save_as(&copy_path).unwrap();
+
+        // Changes made from now on are only saved to the new file
+        let added = create_folder(&client, Ptr::null(), "Added");
+        client.tick(&mut ());
+        drop(client);
+
+        let mut copy = Client::<TestProject>::local(&copy_path).unwrap();
+        for ptr in [parent, child, added] {
+            copy.request_load(ptr);
+        }
+        copy.tick(&mut ());
+        assert_eq!(copy.get(parent).unwrap().name, "Renamed");
+        assert_eq!(copy.get(child).unwrap().name, "Child");
+        assert_eq!(copy.get(added).unwrap().name, "Added");
+        drop(copy);
+        assert!(File::open_read_only::<TestProject>(&copy_path).unwrap().read_bytes(garbage.key).unwrap().is_none());
+
+        let original = Client::<TestProject>::local(&path).unwrap();
+        assert_eq!(original.project().folders.iter().collect::<Vec<_>>(), vec![parent]);
+        drop(original);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&copy_path).unwrap();
+    }
+
+    #[test]
+    fn project_changes_are_reported_once_when_saving_fails() {
+        let path = temp_path("save_fails");
+        let mut client = Client::<TestProject>::with_storage(DirectoryStorage::open_with_format(&path, DirectoryFormat::Json).unwrap()).unwrap();
+        client.tick(&mut ());
+
+        // The project's data can't replace a directory with something in it
+        let blocker = path.join("project.json");
+        std::fs::remove_file(&blocker).unwrap();
+        std::fs::create_dir(&blocker).unwrap();
+        std::fs::write(blocker.join("blocker"), b"").unwrap();
+        client.perform(&mut Action::new(), SetN { n: 3 });
+        let changes = client.tick(&mut ());
+        assert!(changes.project_modified());
+        assert!(changes.save_error().is_some());
+        let changes = client.tick(&mut ());
+        assert!(!changes.project_modified());
+        assert!(changes.save_error().is_some());
+
+        // Saving is still retried until it succeeds
+        std::fs::remove_dir_all(&blocker).unwrap();
+        let changes = client.tick(&mut ());
+        assert!(changes.save_error().is_none());
+        drop(client);
+        assert_eq!(Client::<TestProject>::local(&path).unwrap().project().n, 3);
+
+        std::fs::remove_dir_all(&path).unwrap();
     }
 
 }
@@ -1,8 +1,8 @@
 
 
-use std::{any::{type_name, TypeId}, cell::RefCell};
+use std::{any::{type_name, TypeId}, cell::RefCell, collections::HashMap};
 
-use crate::{Act, Action, Object, Operation, OperationDyn, Project, ProjectContext, ProjectContextMut, Ptr, Recorder};
+use crate::{Act, Action, DeserializationError, Object, ObjectKind, Operation, OperationDyn, Project, ProjectContext, ProjectContextMut, Ptr, Recorder};
 
 mod local;
 use local::*;
@@ -10,6 +10,14 @@ use local::*;
 mod collab;
 use collab::*;
 
+mod changes;
+pub use changes::*;
+
+mod eviction;
+
+mod loading;
+pub use loading::*;
+
 pub(crate) enum ClientKind<P: Project> {
     Local(Local<P>),
     Collab(Collab<P>)
@@ -52,7 +60,19 @@ pub struct Client<P: Project> {
     pub(crate) project: P,
     pub(crate) objects: P::Objects,
     operations_to_perform: RefCell<Vec<Box<dyn OperationDyn<Project = P>>>>,
-    project_modified: bool
+    project_modified: bool,
+    /// Problems encountered while loading data that were skipped over
+    pub(crate) load_warnings: Vec<DeserializationError>,
+    /// The callbacks to call when something changes
+    subscriptions: Subscriptions,
+    /// The maximum estimated memory loaded objects may use before the least recently used ones are evicted
+    memory_budget: Option<usize>,
+    /// The number of ticks so far. Used to find the least recently used objects.
+    curr_tick: u64,
+    /// The parents evicted after their children, by the key of each child. Reloading an evicted child reloads its parents too.
+    evicted_parents: RefCell<HashMap<u64, (&'static ObjectKind<P>, u64)>>,
+    /// The object loads requested by the user that haven't finished yet
+    loads: RefCell<LoadTracker>
 }
 
 impl<P: Project> Client<P> {
@@ -110,11 +130,10 @@ impl<P: Project> Client<P> {
         self.operations_to_perform.borrow_mut().push(operation);
     }
 
-    /// Update the client. Performs all the queued operations. Returns the messages that should be sent to the server.
-    pub fn tick(&mut self, context: &mut P::Context) {
-        let mut operations = self.operations_to_perform.borrow_mut();
-        let operations = &mut *operations;
-        let operations = std::mem::replace(operations, Vec::new());
+    /// Update the client. Performs all the queued operations, and returns everything that changed since the last tick.
+    /// Subscribers are notified of the changes before this returns. If saving the changes to the project file fails, see `ChangeSet::save_error`.
+    pub fn tick(&mut self, context: &mut P::Context) -> ChangeSet<P> {
+        let operations = std::mem::replace(self.operations_to_perform.get_mut(), Vec::new());
 
         // Perform queued operations 
         for operation in operations {
@@ -128,7 +147,7 @@ impl<P: Project> Client<P> {
             let deltas = recorder.deltas;
 
             if let Some(collab) = self.kind.as_collab() {
-                collab.perform_operation(operation, deltas); 
+                collab.perform_operation(operation, deltas, &self.objects); 
             }
         }
 
@@ -136,11 +155,39 @@ impl<P: Project> Client<P> {
             collab.request_keys(); 
         }
 
+        // The project is only reported as modified once, even if saving it fails. Saving it is retried until it succeeds.
+        let project_modified = std::mem::take(&mut self.project_modified);
+        let mut changes = ChangeSet::new(project_modified);
+
+        if let Some(local) = self.kind.as_local() {
+            if let Err(error) = local.save_changes(&mut self.project, &mut self.objects, project_modified) {
+                changes.set_save_error(error);
+            }
+        }
+
+        // Collab clients don't save anything, so just forget the modifications
+        if self.kind.as_collab().is_some() {
+            for object_kind in P::OBJECTS {
+                (object_kind.clear_modifications)(&mut self.objects);
+            }
+        }
+
+        // Modifications are saved before unloading, and objects with modifications that failed to save are kept, so nothing is lost
+        self.unload_objects();
+
         if let Some(local) = self.kind.as_local() {
-            local.save_changes(&mut self.project, &mut self.objects, &mut self.project_modified);
-            local.load_objects(&mut self.objects);
+            let warnings = local.load_objects(&mut self.objects);
+            self.load_warnings.extend(warnings);
         }
-        
+
+        for object_kind in P::OBJECTS {
+            changes.add_kind(object_kind.name, (object_kind.take_changes)(&mut self.objects));
+        }
+        self.subscriptions.notify(&changes);
+        self.resolve_loads();
+
+        self.curr_tick += 1;
+        changes
     }
 
     pub fn take_messages(&self) -> Vec<rmpv::Value> {
@@ -154,21 +201,30 @@ impl<P: Project> Client<P> {
         &self.project
     }
 
+    /// Take the problems encountered while loading data since the last call.
+    /// Data that could not be deserialized is skipped over or replaced with default values, so these should be shown to the user as warnings.
+    pub fn take_load_warnings(&mut self) -> Vec<DeserializationError> {
+        std::mem::replace(&mut self.load_warnings, Vec::new())
+    }
+
     pub fn get<O: Object<Project = P>>(&self, ptr: Ptr<O>) -> Option<&O> {
-        O::list(&self.objects).get(ptr)
+        let list = O::list(&self.objects);
+        let Some(object) = list.get(ptr) else {
+            // Objects evicted to stay within the memory budget are reloaded when they're needed again
+            if list.evicted.borrow_mut().remove(&ptr) {
+                self.request_load(ptr);
+                self.reload_evicted_parents(ptr.key);
+            }
+            return None;
+        };
+        Some(object)
     }
 
-    pub fn request_load<O: Object<Project = P>>(&self, ptr: Ptr<O>) {
-        match &self.kind {
-            ClientKind::Local(_) => { O::list(&self.objects).to_load.borrow_mut().insert(ptr); },
-            ClientKind::Collab(collab) => {
-                collab.send_message(rmpv::Value::Map(vec![
-                    ("type".into(), "load".into()),
-                    ("object".into(), O::NAME.into()),
-                    ("key".into(), ptr.key.into()),
-                ]));
-            },
-        }
+    /// Unload an object from memory on the next tick, after saving any modifications to it.
+    /// If the modifications can't be saved, the object stays loaded until they are.
+    /// The object is not deleted, and can be loaded again using `Client::request_load`.
+    pub fn unload<O: Object<Project = P>>(&self, ptr: Ptr<O>) {
+        O::list(&self.objects).to_unload.borrow_mut().insert(ptr);
     }
 
     pub(crate) fn context(&self) -> ProjectContext<P> {
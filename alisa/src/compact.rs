@@ -0,0 +1,60 @@
+
+use std::path::Path;
+
+use crate::{Error, File, Project};
+
+/// The result of compacting a project file
+#[derive(Clone, Debug, Default)]
+pub struct CompactionReport {
+    /// The size of the file before compacting, in bytes
+    pub original_size: u64,
+    /// The size of the compacted file, in bytes
+    pub compacted_size: u64,
+    /// The number of objects copied into the compacted file
+    pub objects: usize
+}
+
+impl CompactionReport {
+
+    /// The number of bytes freed by compacting the file
+    pub fn reclaimed(&self) -> u64 {
+        self.original_size.saturating_sub(self.compacted_size)
+    }
+
+}
+
+pub(crate) fn not_in_file_error() -> Error {
+    Error::io(std::io::ErrorKind::Unsupported, "in-memory projects are not stored in a file")
+}
+
+/// Write a compacted copy of an open project file to a new file
+pub(crate) fn compact_into<P: Project>(file: &mut File, curr_key: u64, compacted_path: &Path) -> Result<CompactionReport, Error> {
+    let path = file.path().ok_or_else(not_in_file_error)?;
+    if path.is_dir() {
+        return Err(Error::io(std::io::ErrorKind::Unsupported, "project directories can't be compacted"));
+    }
+    let original_size = std::fs::metadata(path)?.len();
+    let (mut compacted, _, _, _, _) = File::open::<P, _>(compacted_path, false)?;
+    let objects = file.copy_into::<P>(&mut compacted, curr_key)?;
+    compacted.commit()?;
+    Ok(CompactionReport {
+        original_size,
+        compacted_size: std::fs::metadata(compacted_path)?.len(),
+        objects
+    })
+}
+
+/// Write a compacted copy of a project file to a new file.
+/// Deleted objects leave free pages behind in the file, and the pages of the remaining objects get scattered over time.
+/// The compacted file holds the same project and objects, under the same keys, packed into as few pages as possible. The original file is not modified.
+/// Objects that can't be reached from the project are left out, unless some of the reachable data can't be read. See `collect_garbage`.
+pub fn compact_file<P: Project, PathRef: AsRef<Path>, CompactedPathRef: AsRef<Path>>(path: PathRef, compacted_path: CompactedPathRef) -> Result<CompactionReport, Error> {
+    if !path.as_ref().exists() {
+        return Err(Error::io(std::io::ErrorKind::NotFound, "file does not exist"));
+    }
+    if compacted_path.as_ref().exists() {
+        return Err(Error::io(std::io::ErrorKind::AlreadyExists, "file already exists"));
+    }
+    let (mut file, _, _, curr_key, _) = File::open::<P, _>(path, false)?;
+    compact_into::<P>(&mut file, curr_key, compacted_path.as_ref())
+}
@@ -0,0 +1,47 @@
+
+use std::path::Path;
+
+use crate::{DirectoryFormat, DirectoryStorage, Error, File, Project, Storage, VerterStorage};
+
+/// Copy an open project into a new storage
+fn convert_into<P: Project>(file: &mut File, curr_key: u64, storage: Box<dyn Storage>) -> Result<usize, Error> {
+    let mut converted = File::from_storage(storage);
+    let objects = file.copy_into::<P>(&mut converted, curr_key)?;
+    converted.commit()?;
+    Ok(objects)
+}
+
+/// Convert a project file into a project directory, with the project and every object in a separate file. See `DirectoryStorage`.
+/// Keys are preserved, so the project is unchanged by converting it back with `convert_to_file`. Returns the number of objects converted.
+pub fn convert_to_directory<P: Project, PathRef: AsRef<Path>, DirectoryPathRef: AsRef<Path>>(path: PathRef, directory_path: DirectoryPathRef, format: DirectoryFormat) -> Result<usize, Error> {
+    let directory_path = directory_path.as_ref();
+    if !path.as_ref().is_file() {
+        return Err(Error::io(std::io::ErrorKind::NotFound, "file does not exist"));
+    }
+    if directory_path.exists() {
+        return Err(Error::io(std::io::ErrorKind::AlreadyExists, "directory already exists"));
+    }
+    let (mut file, _, _, curr_key, _) = File::open::<P, _>(path, false)?;
+    let result = DirectoryStorage::open_with_format(directory_path, format).and_then(|storage| convert_into::<P>(&mut file, curr_key, Box::new(storage)));
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(directory_path);
+    }
+    result
+}
+
+/// Convert a project directory written by `DirectoryStorage` into a project file. Returns the number of objects converted.
+pub fn convert_to_file<P: Project, DirectoryPathRef: AsRef<Path>, PathRef: AsRef<Path>>(directory_path: DirectoryPathRef, path: PathRef) -> Result<usize, Error> {
+    let path = path.as_ref();
+    if !directory_path.as_ref().is_dir() {
+        return Err(Error::io(std::io::ErrorKind::NotFound, "directory does not exist"));
+    }
+    if path.exists() {
+        return Err(Error::io(std::io::ErrorKind::AlreadyExists, "file already exists"));
+    }
+    let (mut directory, _, _, curr_key, _) = File::open::<P, _>(directory_path, false)?;
+    let result = VerterStorage::open(path, P::verter_config()).and_then(|storage| convert_into::<P>(&mut directory, curr_key, Box::new(storage)));
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
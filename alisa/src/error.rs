@@ -0,0 +1,78 @@
+
+use std::fmt::Display;
+
+use crate::DeserializationError;
+
+/// A problem opening, reading or saving a project
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from or writing to the disk failed
+    Io(std::io::Error),
+    /// The file is not a project file of this kind, or is not a project file at all
+    BadMagic,
+    /// The root data of the file, which says where everything else in the file is, could not be read
+    CorruptRoot,
+    /// Some data could not be decoded or deserialized
+    Decode(DeserializationError),
+    /// The data was written by a newer version of the project format than the one we know how to read
+    VersionMismatch {
+        /// The version the data was written with
+        found: u64,
+        /// The newest version this build of the app can read, `Project::VERSION`
+        supported: u64
+    }
+}
+
+impl Error {
+
+    pub(crate) fn decode(message: &str) -> Self {
+        Self::Decode(DeserializationError::new(&[], message.to_owned()))
+    }
+
+    pub(crate) fn io(kind: std::io::ErrorKind, message: &str) -> Self {
+        Self::Io(std::io::Error::new(kind, message.to_owned()))
+    }
+
+}
+
+impl Display for Error {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::BadMagic => write!(f, "not a valid project file"),
+            Error::CorruptRoot => write!(f, "could not read file root data"),
+            Error::Decode(error) => write!(f, "{}", error),
+            Error::VersionMismatch { found, supported } => write!(f, "data was written by a newer version of the project format ({} > {})", found, supported),
+        }
+    }
+
+}
+
+impl std::error::Error for Error {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Decode(error) => Some(error),
+            _ => None
+        }
+    }
+
+}
+
+impl From<std::io::Error> for Error {
+
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+
+}
+
+impl From<DeserializationError> for Error {
+
+    fn from(error: DeserializationError) -> Self {
+        Self::Decode(error)
+    }
+
+}
@@ -0,0 +1,345 @@
+
+use std::{collections::{HashMap, HashSet}, io, path::{Path, PathBuf}};
+
+use crate::{json_to_rmpv, rmpv_decode, rmpv_encode, rmpv_to_json, Error};
+
+use super::Storage;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// How the files in a project directory are encoded
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DirectoryFormat {
+    /// Pretty-printed JSON, which is easy to read, diff and merge
+    #[default]
+    Json,
+    MessagePack
+}
+
+impl DirectoryFormat {
+
+    fn extension(&self) -> &'static str {
+        match self {
+            DirectoryFormat::Json => "json",
+            DirectoryFormat::MessagePack => "msgpack",
+        }
+    }
+
+    fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "json" => Some(DirectoryFormat::Json),
+            "msgpack" => Some(DirectoryFormat::MessagePack),
+            _ => None
+        }
+    }
+
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            DirectoryFormat::Json => {
+                let Some(data) = rmpv_decode(data) else {
+                    return Err(Error::decode("could not decode data"));
+                };
+                let mut json = serde_json::to_vec_pretty(&rmpv_to_json(&data)).map_err(|error| Error::decode(&error.to_string()))?;
+                json.push(b'\n');
+                Ok(json)
+            },
+            DirectoryFormat::MessagePack => Ok(data.to_vec()),
+        }
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            DirectoryFormat::Json => {
+                let data: serde_json::Value = serde_json::from_slice(data).ok()?;
+                rmpv_encode(&json_to_rmpv(&data)?)
+            },
+            DirectoryFormat::MessagePack => Some(data.to_vec()),
+        }
+    }
+
+}
+
+/// Write a file by writing a temporary file next to it and moving it into place, so the file is never left half-written
+fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut temp_path = path.to_owned().into_os_string();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    std::fs::write(&temp_path, data)?;
+    std::fs::rename(&temp_path, path)
+}
+
+/// Stores a project in a directory, with the project and every object in a separate file.
+/// Objects are stored in a directory per kind and named by key, like `Slide/000000000042.json`, next to `project.json` and a `manifest.json` holding the next available key.
+/// Since a change to an object only touches the object's file, project directories work well with version control.
+///
+/// `Client::local` opens existing project directories. Use `Client::with_storage` to create a new one, or `convert_to_directory` to convert a project file.
+pub struct DirectoryStorage {
+    path: PathBuf,
+    format: DirectoryFormat,
+    /// The kind of every object stored in the directory, by key
+    objects: HashMap<u64, String>,
+    curr_key: u64,
+    version: u64,
+
+    /// The project data written since the last commit
+    project: Option<Vec<u8>>,
+    /// The object writes since the last commit, along with the kind of each object
+    writes: HashMap<u64, (String, Vec<u8>)>,
+    /// The objects deleted since the last commit
+    deletes: HashSet<u64>,
+    /// Does the manifest need to be updated?
+    manifest_modified: bool
+}
+
+impl DirectoryStorage {
+
+    /// Open a project directory, creating it if it doesn't exist. New project directories are written as JSON.
+    pub fn open<PathRef: AsRef<Path>>(path: PathRef) -> Result<Self, Error> {
+        Self::open_with_format(path, DirectoryFormat::default())
+    }
+
+    /// Open a project directory, creating it with the given format if it doesn't exist.
+    /// Existing project directories keep the format they were written with.
+    pub fn open_with_format<PathRef: AsRef<Path>>(path: PathRef, format: DirectoryFormat) -> Result<Self, Error> {
+        let path = path.as_ref().to_owned();
+        std::fs::create_dir_all(&path)?;
+
+        let mut storage = Self {
+            path,
+            format,
+            objects: HashMap::new(),
+            curr_key: 1,
+            version: 0,
+            project: None,
+            writes: HashMap::new(),
+            deletes: HashSet::new(),
+            manifest_modified: false
+        };
+
+        let manifest = match std::fs::read(storage.path.join(MANIFEST_FILE)) {
+            Ok(manifest) => manifest,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                // Never treat a directory with other things in it as a new project
+                if std::fs::read_dir(&storage.path)?.next().is_some() {
+                    return Err(Error::BadMagic);
+                }
+                storage.manifest_modified = true;
+                return Ok(storage);
+            },
+            Err(error) => return Err(error.into())
+        };
+        let Ok(manifest) = serde_json::from_slice::<serde_json::Value>(&manifest) else {
+            return Err(Error::CorruptRoot);
+        };
+        let Some(curr_key) = manifest.get("curr_key").and_then(serde_json::Value::as_u64) else {
+            return Err(Error::CorruptRoot);
+        };
+        let Some(format) = manifest.get("format").and_then(serde_json::Value::as_str).and_then(DirectoryFormat::from_extension) else {
+            return Err(Error::CorruptRoot);
+        };
+        storage.curr_key = curr_key;
+        storage.format = format;
+        storage.version = manifest.get("version").and_then(serde_json::Value::as_u64).unwrap_or(0);
+
+        // Find the objects in the directory. Every directory holds the objects of one kind.
+        for kind_entry in std::fs::read_dir(&storage.path)? {
+            let kind_entry = kind_entry?;
+            if !kind_entry.file_type()?.is_dir() {
+                continue;
+            }
+            let Some(kind) = kind_entry.file_name().to_str().map(str::to_owned) else { continue; };
+            for object_entry in std::fs::read_dir(kind_entry.path())? {
+                let object_path = object_entry?.path();
+                if object_path.extension().and_then(|extension| extension.to_str()) != Some(format.extension()) {
+                    continue;
+                }
+                let Some(key) = object_path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u64>().ok()) else { continue; };
+                storage.objects.insert(key, kind.clone());
+            }
+        }
+
+        Ok(storage)
+    }
+
+    pub fn format(&self) -> DirectoryFormat {
+        self.format
+    }
+
+    fn project_path(&self) -> PathBuf {
+        self.path.join(format!("project.{}", self.format.extension()))
+    }
+
+    fn object_path(&self, kind: &str, key: u64) -> PathBuf {
+        self.path.join(kind).join(format!("{:012}.{}", key, self.format.extension()))
+    }
+
+    fn read_file(&self, path: &Path) -> Result<Option<Vec<u8>>, Error> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into())
+        };
+        match self.format.decode(&data) {
+            Some(data) => Ok(Some(data)),
+            None => Err(Error::decode(&format!("could not decode {}", path.display())))
+        }
+    }
+
+    fn write_manifest(&self) -> Result<(), Error> {
+        let manifest = serde_json::json!({
+            "format": self.format.extension(),
+            "curr_key": self.curr_key,
+            "version": self.version
+        });
+        let mut manifest = serde_json::to_vec_pretty(&manifest).map_err(|error| Error::decode(&error.to_string()))?;
+        manifest.push(b'\n');
+        Ok(write_file(&self.path.join(MANIFEST_FILE), &manifest)?)
+    }
+
+}
+
+impl Storage for DirectoryStorage {
+
+    fn read_project(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        if let Some(project) = &self.project {
+            return Ok(Some(project.clone()));
+        }
+        self.read_file(&self.project_path())
+    }
+
+    fn write_project(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.project = Some(data.to_vec());
+        Ok(())
+    }
+
+    fn read_object(&mut self, key: u64) -> Result<Option<Vec<u8>>, Error> {
+        if let Some((_, data)) = self.writes.get(&key) {
+            return Ok(Some(data.clone()));
+        }
+        if self.deletes.contains(&key) {
+            return Ok(None);
+        }
+        let Some(kind) = self.objects.get(&key) else {
+            return Ok(None);
+        };
+        self.read_file(&self.object_path(kind, key))
+    }
+
+    fn write_object(&mut self, kind: &str, key: u64, data: &[u8]) -> Result<(), Error> {
+        self.deletes.remove(&key);
+        self.writes.insert(key, (kind.to_owned(), data.to_vec()));
+        Ok(())
+    }
+
+    fn delete_object(&mut self, key: u64) -> Result<(), Error> {
+        self.writes.remove(&key);
+        self.deletes.insert(key);
+        Ok(())
+    }
+
+    fn keys(&mut self) -> Result<Vec<u64>, Error> {
+        let keys = self.objects.keys().chain(self.writes.keys()).copied().collect::<HashSet<_>>();
+        Ok(keys.into_iter().filter(|key| !self.deletes.contains(key)).collect())
+    }
+
+    fn curr_key(&self) -> u64 {
+        self.curr_key
+    }
+
+    fn set_curr_key(&mut self, curr_key: u64) -> Result<(), Error> {
+        self.curr_key = curr_key;
+        self.manifest_modified = true;
+        Ok(())
+    }
+
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) -> Result<(), Error> {
+        self.version = version;
+        self.manifest_modified = true;
+        Ok(())
+    }
+
+    /// Write every changed file. Each file is replaced as a whole, but a commit that fails partway leaves some of the files updated.
+    /// The changes that weren't written are kept, and writing them is retried on the next commit.
+    fn commit(&mut self) -> Result<(), Error> {
+        if let Some(project) = &self.project {
+            write_file(&self.project_path(), &self.format.encode(project)?)?;
+            self.project = None;
+        }
+
+        for key in self.writes.keys().copied().collect::<Vec<_>>() {
+            let (kind, data) = &self.writes[&key];
+            let data = self.format.encode(data)?;
+            write_file(&self.object_path(kind, key), &data)?;
+            let (kind, _) = self.writes.remove(&key).unwrap();
+            // An object written with a different kind than before moves to the directory of its new kind
+            if let Some(old_kind) = self.objects.insert(key, kind.clone()) {
+                if old_kind != kind {
+                    let _ = std::fs::remove_file(self.object_path(&old_kind, key));
+                }
+            }
+        }
+
+        for key in self.deletes.iter().copied().collect::<Vec<_>>() {
+            if let Some(kind) = self.objects.get(&key) {
+                match std::fs::remove_file(self.object_path(kind, key)) {
+                    Ok(()) => {},
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+                    Err(error) => return Err(error.into())
+                }
+                self.objects.remove(&key);
+            }
+            self.deletes.remove(&key);
+        }
+
+        // The manifest is written last, so a project directory with a manifest always has its project data
+        if self.manifest_modified {
+            self.write_manifest()?;
+            self.manifest_modified = false;
+        }
+        Ok(())
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rmpv_decode, rmpv_encode, test_project::{temp_path, TestProject}, DeserializationContext, Serializable, SerializationContext, Storage};
+
+    use super::{DirectoryFormat, DirectoryStorage};
+
+    #[test]
+    fn floats_round_trip() {
+        for format in [DirectoryFormat::Json, DirectoryFormat::MessagePack] {
+            let path = temp_path("directory_floats");
+            let floats = (f32::NAN, f64::INFINITY, 0.1f32, f64::NEG_INFINITY);
+            let data = rmpv_encode(&Serializable::<TestProject>::serialize(&floats, &SerializationContext::shallow())).unwrap();
+            {
+                let mut storage = DirectoryStorage::open_with_format(&path, format).unwrap();
+                storage.write_object("Folder", 1, &data).unwrap();
+                storage.commit().unwrap();
+            }
+
+            let mut storage = DirectoryStorage::open(&path).unwrap();
+            let stored = storage.read_object(1).unwrap().unwrap();
+            assert_eq!(stored, data);
+            let mut context = DeserializationContext::<TestProject>::data();
+            let (a, b, c, d) = <(f32, f64, f32, f64)>::deserialize(&rmpv_decode(&stored).unwrap(), &mut context).unwrap();
+            assert!(a.is_nan());
+            assert_eq!((b, c, d), (f64::INFINITY, 0.1, f64::NEG_INFINITY));
+
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+    }
+
+}
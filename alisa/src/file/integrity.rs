@@ -0,0 +1,317 @@
+
+use std::{collections::HashSet, fmt::Display, path::Path};
+
+use crate::{migrate, rmpv_decode, DeserializationContext, DeserializationError, Error, Project, SerializationContext};
+
+use super::{File, Storage, VerterStorage, UNREACHABLE_KIND};
+
+/// A problem found in a project file by `check_file`
+#[derive(Clone, Debug)]
+pub enum IntegrityIssue {
+    /// The root data of the file could not be read, so nothing else in the file could be found
+    UnreadableRoot,
+    /// A node of the tree mapping object keys to their place in the file could not be read.
+    /// The objects below the node can't be found.
+    CorruptKeymapNode {
+        node_ptr: u64
+    },
+    /// The keymap points to a page that could not be read
+    DanglingKeymapEntry {
+        key: u64,
+        ptr: u64
+    },
+    /// An object's data is not valid MessagePack
+    UndecodableObject {
+        key: u64
+    },
+    /// The project data could not be read or decoded
+    UnreadableProject,
+    /// A pointer refers to an object that isn't in the file
+    DanglingPtr {
+        path: String,
+        object: &'static str,
+        key: u64
+    },
+    /// Some data could not be deserialized
+    InvalidData(DeserializationError),
+    /// An unreachable object was left out of the repaired file by `repair_file`.
+    /// The file is from an older version of the project format, and the object can't be migrated since its kind is unknown.
+    UnmigratableObject {
+        key: u64
+    }
+}
+
+impl Display for IntegrityIssue {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntegrityIssue::UnreadableRoot => write!(f, "could not read file root data"),
+            IntegrityIssue::CorruptKeymapNode { node_ptr } => write!(f, "could not read keymap node at {}", node_ptr),
+            IntegrityIssue::DanglingKeymapEntry { key, ptr } => write!(f, "object {} points to unreadable page {}", key, ptr),
+            IntegrityIssue::UndecodableObject { key } => write!(f, "object {} is not valid MessagePack", key),
+            IntegrityIssue::UnreadableProject => write!(f, "could not read project data"),
+            IntegrityIssue::DanglingPtr { path, object, key } => write!(f, "{}: {}[{}] is not in the file", path, object, key),
+            IntegrityIssue::InvalidData(error) => write!(f, "{}", error),
+            IntegrityIssue::UnmigratableObject { key } => write!(f, "unreachable object {} could not be migrated and was left out", key),
+        }
+    }
+
+}
+
+/// The result of checking a project file
+#[derive(Clone, Debug, Default)]
+pub struct IntegrityReport {
+    pub issues: Vec<IntegrityIssue>,
+    /// The number of objects that could be read from the file
+    pub objects: usize,
+    /// The keys of the readable objects that can't be reached from the project. See `collect_garbage`.
+    pub unreachable: Vec<u64>
+}
+
+impl IntegrityReport {
+
+    /// Is the file free of problems? Unreachable objects are not considered a problem.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+}
+
+/// Everything that could be read from a project file
+struct Salvage<P: Project> {
+    report: IntegrityReport,
+    project: Option<P>,
+    objects: P::Objects,
+    /// The readable objects that can't be reached from the project, by key
+    unreachable: Vec<(u64, rmpv::Value)>,
+    curr_key: u64,
+    /// Is the data in the file from an older version of the project format?
+    migrated: bool
+}
+
+/// Read everything possible from a project file without writing to it
+fn salvage<P: Project>(path: &Path) -> Result<Salvage<P>, Error> {
+    if !path.exists() {
+        return Err(Error::io(std::io::ErrorKind::NotFound, "file does not exist"));
+    }
+    // The checks walk the structure of Verter files, which project directories don't have
+    if path.is_dir() {
+        return Err(Error::io(std::io::ErrorKind::Unsupported, "project directories can't be checked or repaired"));
+    }
+
+    let mut salvage = Salvage {
+        report: IntegrityReport::default(),
+        project: None,
+        objects: P::Objects::default(),
+        unreachable: Vec::new(),
+        curr_key: 1,
+        migrated: false
+    };
+
+    // A batch left in the journal is read as if it were saved, but not applied, so the file isn't modified
+    let Some(mut storage) = VerterStorage::open_existing(path, P::verter_config())? else {
+        salvage.report.issues.push(IntegrityIssue::UnreadableRoot);
+        return Ok(salvage);
+    };
+    if storage.version() > P::VERSION {
+        return Err(Error::VersionMismatch { found: storage.version(), supported: P::VERSION });
+    }
+    salvage.curr_key = storage.curr_key();
+
+    // Walk the keymap and read every object
+    let (entries, corrupt_nodes) = storage.check_keymap();
+    for node_ptr in corrupt_nodes {
+        salvage.report.issues.push(IntegrityIssue::CorruptKeymapNode { node_ptr });
+    }
+    let mut readable = Vec::new();
+    let mut in_keymap = HashSet::new();
+    for (key, ptr) in entries {
+        in_keymap.insert(key);
+        salvage.curr_key = salvage.curr_key.max(key + 1);
+        let Some(data) = storage.read_page(ptr) else {
+            salvage.report.issues.push(IntegrityIssue::DanglingKeymapEntry { key, ptr });
+            continue;
+        };
+        let Some(data) = rmpv_decode(&data) else {
+            salvage.report.issues.push(IntegrityIssue::UndecodableObject { key });
+            continue;
+        };
+        readable.push((key, data));
+    }
+    salvage.report.objects = readable.len();
+
+    let mut file = File::from_storage(Box::new(storage));
+    salvage.migrated = file.is_migrating::<P>();
+
+    // Walk everything reachable from the project
+    let Ok(Some(mut project_data)) = file.read_project() else {
+        salvage.report.issues.push(IntegrityIssue::UnreadableProject);
+        salvage.unreachable = readable;
+        return Ok(salvage);
+    };
+    if file.is_migrating::<P>() {
+        migrate(P::MIGRATIONS, &mut project_data, file.version());
+    }
+    let (project, errors) = {
+        let mut context = DeserializationContext::local(&mut salvage.objects, &mut file).load_ptr_targets(true);
+        let project = P::deserialize(&project_data, &mut context);
+        (project, context.take_errors())
+    };
+    for error in errors {
+        match error.unavailable_object() {
+            // Objects that are in the keymap but unreadable were already reported
+            Some((_, key)) if in_keymap.contains(&key) => {},
+            Some((object, key)) => salvage.report.issues.push(IntegrityIssue::DanglingPtr { path: error.path().to_owned(), object, key }),
+            None => salvage.report.issues.push(IntegrityIssue::InvalidData(error)),
+        }
+    }
+    salvage.project = project;
+
+    let reachable = P::OBJECTS.iter().flat_map(|object_kind| (object_kind.keys)(&salvage.objects)).collect::<HashSet<_>>();
+    salvage.unreachable = readable.into_iter().filter(|(key, _)| !reachable.contains(key)).collect();
+    salvage.report.unreachable = salvage.unreachable.iter().map(|(key, _)| *key).collect();
+    salvage.report.unreachable.sort();
+
+    Ok(salvage)
+}
+
+/// Check a project file for problems without modifying it.
+/// Walks the root data, the keymap and every object in the file, as well as everything reachable from the project.
+/// Only project files can be checked. Fails for project directories written by `DirectoryStorage`.
+pub fn check_file<P: Project, PathRef: AsRef<Path>>(path: PathRef) -> Result<IntegrityReport, Error> {
+    Ok(salvage::<P>(path.as_ref())?.report)
+}
+
+/// Copy everything that can be read from a damaged project file into a new file. The original file is never modified.
+/// Data that can't be deserialized is replaced with default values, and readable objects that can't be reached from the project are kept.
+/// If the project data itself can't be read, an empty project is written instead. Returns the problems found in the original file,
+/// along with the unreachable objects that had to be left out because the file needed migrating.
+/// Only project files can be repaired. Fails for project directories written by `DirectoryStorage`.
+pub fn repair_file<P: Project, PathRef: AsRef<Path>, RepairedPathRef: AsRef<Path>>(path: PathRef, repaired_path: RepairedPathRef) -> Result<IntegrityReport, Error> {
+    if repaired_path.as_ref().exists() {
+        return Err(Error::io(std::io::ErrorKind::AlreadyExists, "file already exists"));
+    }
+    let mut salvage = salvage::<P>(path.as_ref())?;
+
+    let (mut file, _, _, _, _) = File::open::<P, _>(repaired_path, false)?;
+    let project = salvage.project.unwrap_or_else(P::empty);
+    file.write_project(&project.serialize(&SerializationContext::shallow()))?;
+
+    let mut objects = Vec::new();
+    for object_kind in P::OBJECTS {
+        objects.extend((object_kind.serialize_all)(&salvage.objects).into_iter().map(|(key, data)| (object_kind.name, key, data)));
+    }
+    // Unreachable objects can't be migrated, since we don't know what kind of object they are
+    if salvage.migrated {
+        salvage.report.issues.extend(salvage.unreachable.iter().map(|(key, _)| IntegrityIssue::UnmigratableObject { key: *key }));
+    } else {
+        objects.extend(salvage.unreachable.into_iter().map(|(key, data)| (UNREACHABLE_KIND, key, data)));
+    }
+    for (kind, key, data) in objects {
+        file.write(kind, key, &data)?;
+    }
+    file.set_curr_key(salvage.curr_key)?;
+    file.commit()?;
+
+    Ok(salvage.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{test_project::{temp_path, Folder, TestProject}, Children, Client, Error, File, Object, Project, Ptr, Serializable, SerializationContext, Storage, UnorderedChildList};
+
+    use super::{check_file, repair_file, IntegrityIssue, VerterStorage};
+
+    /// Write a project with folders 1 and 300, and folder 2 inside folder 1.
+    /// Keys 1 and 2 share a keymap node at the bottom of the tree, while key 300 has its own.
+    fn write_project(path: &Path) -> Result<(), Error> {
+        let (mut file, _, _, _, _) = File::open::<TestProject, _>(path, false)?;
+        let folder = |parent: u64, name: &str, children: &[u64]| {
+            let mut folders = UnorderedChildList::new();
+            for child in children {
+                folders.insert((), Ptr::from_key(*child));
+            }
+            Folder { parent: Ptr::from_key(parent), name: name.to_owned(), folders }.serialize(&SerializationContext::shallow())
+        };
+        let mut project = TestProject::empty();
+        project.folders.insert((), Ptr::from_key(1));
+        project.folders.insert((), Ptr::from_key(300));
+        file.write_project(&project.serialize(&SerializationContext::shallow()))?;
+        file.write(Folder::NAME, 1, &folder(0, "Folder 1", &[2]))?;
+        file.write(Folder::NAME, 2, &folder(1, "Folder 2", &[]))?;
+        file.write(Folder::NAME, 300, &folder(0, "Folder 300", &[]))?;
+        file.set_curr_key(301)?;
+        file.commit()
+    }
+
+    /// Open a repaired file, returning the names of the folders in it
+    fn folder_names(path: &Path) -> Vec<String> {
+        let mut client = Client::<TestProject>::local(path).unwrap();
+        for key in [1, 2, 300] {
+            client.request_load(Ptr::<Folder>::from_key(key));
+        }
+        client.tick(&mut ());
+        let mut names = client.objects.folders.iter().map(|(_, folder)| folder.name.clone()).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn undecodable_objects_are_reported_and_left_out() -> Result<(), Error> {
+        let path = temp_path("undecodable_object");
+        let repaired_path = temp_path("undecodable_object_repaired");
+        write_project(&path)?;
+        let mut storage = VerterStorage::open(&path, TestProject::verter_config())?;
+        // An array of two values, cut off before the values
+        storage.write_object(Folder::NAME, 2, &[0x92])?;
+        storage.commit()?;
+        drop(storage);
+
+        let report = check_file::<TestProject, _>(&path)?;
+        assert!(matches!(report.issues[..], [IntegrityIssue::UndecodableObject { key: 2 }]), "{:?}", report.issues);
+        assert_eq!(report.objects, 2);
+
+        let report = repair_file::<TestProject, _, _>(&path, &repaired_path)?;
+        assert_eq!(report.issues.len(), 1);
+        assert!(check_file::<TestProject, _>(&repaired_path)?.is_ok());
+        assert_eq!(folder_names(&repaired_path), vec!["Folder 1", "Folder 300"]);
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(&repaired_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_keymap_nodes_are_reported_and_skipped() -> Result<(), Error> {
+        let path = temp_path("corrupt_keymap_node");
+        let repaired_path = temp_path("corrupt_keymap_node_repaired");
+        write_project(&path)?;
+        VerterStorage::open(&path, TestProject::verter_config())?.corrupt_keymap_node(300)?;
+
+        // The folder below the corrupt node can't be found anymore
+        let report = check_file::<TestProject, _>(&path)?;
+        assert_eq!(report.issues.len(), 2, "{:?}", report.issues);
+        assert!(matches!(report.issues[0], IntegrityIssue::CorruptKeymapNode { .. }));
+        assert!(matches!(report.issues[1], IntegrityIssue::DanglingPtr { key: 300, .. }));
+        assert_eq!(report.objects, 2);
+
+        repair_file::<TestProject, _, _>(&path, &repaired_path)?;
+        assert!(check_file::<TestProject, _>(&repaired_path)?.is_ok());
+        assert_eq!(folder_names(&repaired_path), vec!["Folder 1", "Folder 2"]);
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(&repaired_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn project_directories_are_not_checked() {
+        let path = temp_path("check_directory");
+        std::fs::create_dir(&path).unwrap();
+        assert!(check_file::<TestProject, _>(&path).is_err());
+        std::fs::remove_dir(&path).unwrap();
+    }
+
+}
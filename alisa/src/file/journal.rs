@@ -0,0 +1,194 @@
+
+use std::{collections::{HashMap, HashSet}, io};
+
+use crate::{rmpv_decode, rmpv_encode, rmpv_get, Error};
+
+/// A Verter file whose writes are held back until they are committed together.
+///
+/// Committing first writes the whole batch to a journal page, then points the root of the Verter file at the journal before applying the batch.
+/// If the app stops while the batch is being applied, the journal is found when the file is next opened and the batch is applied again,
+/// so the file always contains either all of a batch or none of it.
+pub(crate) struct JournaledFile {
+    file: verter::File,
+    /// The page writes since the last commit
+    writes: HashMap<u64, Vec<u8>>,
+    /// The pages deleted since the last commit. These are only freed once the rest of the batch is saved.
+    deletes: HashSet<u64>,
+    /// The root data written since the last commit
+    root: Option<Vec<u8>>,
+    /// The journal page the root of the Verter file points to, if a batch is being applied
+    journal_ptr: Option<u64>,
+    /// Is the current batch already written to the journal page?
+    journaled: bool
+}
+
+fn journal_marker(journal_ptr: u64) -> Option<Vec<u8>> {
+    rmpv_encode(&rmpv::Value::Map(vec![
+        ("journal_ptr".into(), journal_ptr.into())
+    ]))
+}
+
+fn encode_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+impl JournaledFile {
+
+    /// Wrap a Verter file, picking up the batch being saved when the app last stopped, if any.
+    /// The batch is not applied until the next commit, so nothing is written to the file yet.
+    pub fn open(file: verter::File) -> Result<Self, Error> {
+        let mut journaled_file = Self {
+            file,
+            writes: HashMap::new(),
+            deletes: HashSet::new(),
+            root: None,
+            journal_ptr: None,
+            journaled: false
+        };
+
+        let root_data = journaled_file.file.read_root()?;
+        let Some(journal_ptr) = rmpv_decode(&root_data).and_then(|root_data| rmpv_get(&root_data, "journal_ptr")?.as_u64()) else {
+            return Ok(journaled_file);
+        };
+        let journal = journaled_file.file.read(journal_ptr)?;
+        let Some(journal) = rmpv_decode(&journal) else {
+            return Err(Error::CorruptRoot);
+        };
+        let Some(root) = rmpv_get(&journal, "root").and_then(rmpv::Value::as_slice) else {
+            return Err(Error::CorruptRoot);
+        };
+        journaled_file.root = Some(root.to_vec());
+        for write in rmpv_get(&journal, "writes").and_then(rmpv::Value::as_array).map(Vec::as_slice).unwrap_or_default() {
+            let Some([ptr, data]) = write.as_array().map(Vec::as_slice) else { return Err(Error::CorruptRoot); };
+            let (Some(ptr), Some(data)) = (ptr.as_u64(), data.as_slice()) else { return Err(Error::CorruptRoot); };
+            journaled_file.writes.insert(ptr, data.to_vec());
+        }
+        for delete in rmpv_get(&journal, "deletes").and_then(rmpv::Value::as_array).map(Vec::as_slice).unwrap_or_default() {
+            let Some(ptr) = delete.as_u64() else { return Err(Error::CorruptRoot); };
+            journaled_file.deletes.insert(ptr);
+        }
+        journaled_file.journal_ptr = Some(journal_ptr);
+        journaled_file.journaled = true;
+
+        Ok(journaled_file)
+    }
+
+    /// Are there changes waiting to be committed?
+    pub fn has_changes(&self) -> bool {
+        !self.writes.is_empty() || !self.deletes.is_empty() || self.root.is_some()
+    }
+
+    pub fn alloc(&mut self) -> io::Result<u64> {
+        self.file.alloc()
+    }
+
+    pub fn read(&mut self, ptr: u64) -> io::Result<Vec<u8>> {
+        if let Some(data) = self.writes.get(&ptr) {
+            return Ok(data.clone());
+        }
+        if self.deletes.contains(&ptr) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "page was deleted"));
+        }
+        self.file.read(ptr)
+    }
+
+    pub fn write(&mut self, ptr: u64, data: &[u8]) -> io::Result<()> {
+        self.deletes.remove(&ptr);
+        self.writes.insert(ptr, data.to_vec());
+        self.journaled = false;
+        Ok(())
+    }
+
+    pub fn delete(&mut self, ptr: u64) -> io::Result<()> {
+        self.writes.remove(&ptr);
+        self.deletes.insert(ptr);
+        self.journaled = false;
+        Ok(())
+    }
+
+    pub fn read_root(&mut self) -> io::Result<Vec<u8>> {
+        match &self.root {
+            Some(root) => Ok(root.clone()),
+            None => self.file.read_root(),
+        }
+    }
+
+    pub fn write_root(&mut self, data: &[u8]) -> io::Result<()> {
+        self.root = Some(data.to_vec());
+        self.journaled = false;
+        Ok(())
+    }
+
+    /// Write the current batch to a new journal page and point the root of the Verter file at it.
+    /// Once this succeeds, the batch is saved even if applying it fails.
+    fn write_journal(&mut self) -> io::Result<()> {
+        // The root of the Verter file is replaced by the journal marker, so the journal needs to hold the whole root
+        let root = self.read_root()?;
+        self.root = Some(root.clone());
+
+        let journal = rmpv::Value::Map(vec![
+            ("writes".into(), rmpv::Value::Array(self.writes.iter().map(|(ptr, data)| rmpv::Value::Array(vec![(*ptr).into(), rmpv::Value::Binary(data.clone())])).collect())),
+            ("deletes".into(), rmpv::Value::Array(self.deletes.iter().map(|ptr| (*ptr).into()).collect())),
+            ("root".into(), rmpv::Value::Binary(root))
+        ]);
+        let journal = rmpv_encode(&journal).ok_or_else(|| encode_error("could not encode journal"))?;
+        let journal_ptr = self.file.alloc()?;
+        self.file.write(journal_ptr, &journal)?;
+
+        let marker = journal_marker(journal_ptr).ok_or_else(|| encode_error("could not encode journal marker"))?;
+        self.file.write_root(&marker)?;
+
+        // A journal left over from a batch that failed to apply is replaced by the new one
+        if let Some(old_journal_ptr) = self.journal_ptr.replace(journal_ptr) {
+            self.deletes.insert(old_journal_ptr);
+        }
+        self.journaled = true;
+        Ok(())
+    }
+
+    /// Apply the journaled batch to the Verter file
+    fn apply(&mut self) -> io::Result<()> {
+        for (ptr, data) in &self.writes {
+            self.file.write(*ptr, data)?;
+        }
+        if let Some(root) = &self.root {
+            self.file.write_root(root)?;
+        }
+        self.writes.clear();
+        self.root = None;
+
+        // The batch is fully saved now, so the deleted pages and the journal page can be freed.
+        // Failing to free a page only wastes space, so we don't retry it, since freeing a page twice could corrupt the file.
+        if let Some(journal_ptr) = self.journal_ptr.take() {
+            self.deletes.insert(journal_ptr);
+        }
+        for ptr in std::mem::replace(&mut self.deletes, HashSet::new()) {
+            let _ = self.file.delete(ptr);
+        }
+        self.journaled = false;
+        Ok(())
+    }
+
+    /// Journal the current batch but only apply the first `applied` page writes, as if the app stopped partway through a commit
+    #[cfg(test)]
+    pub(super) fn commit_partially(&mut self, applied: usize) -> io::Result<()> {
+        self.write_journal()?;
+        for (ptr, data) in self.writes.iter().take(applied) {
+            self.file.write(*ptr, data)?;
+        }
+        Ok(())
+    }
+
+    /// Atomically save all the changes since the last commit.
+    /// If this fails, the changes are kept and saving them is retried on the next commit.
+    pub fn commit(&mut self) -> io::Result<()> {
+        if !self.has_changes() {
+            return Ok(());
+        }
+        if !self.journaled {
+            self.write_journal()?;
+        }
+        self.apply()
+    }
+
+}
@@ -1,5 +1,7 @@
 
-use std::collections::HashMap;
+use std::{collections::HashMap, io};
+
+use super::JournaledFile;
 
 struct KeyTreeNode {
     children: [u64; 256],
@@ -43,8 +45,8 @@ impl KeyTreeNode {
         result
     }
 
-    fn save(&self, file: &mut verter::File, ptr: u64) {
-        let _ = file.write(ptr, &self.serialize());
+    fn save(&self, file: &mut JournaledFile, ptr: u64) -> io::Result<()> {
+        file.write(ptr, &self.serialize())
     }
 
 }
@@ -71,25 +73,25 @@ impl Keymap {
         }
     }
 
-    pub fn create_empty(file: &mut verter::File) -> Option<(Self, u64)> {
-        let root_ptr = file.alloc().ok()?;
-        Some((Self::new(root_ptr), root_ptr))
+    pub fn create_empty(file: &mut JournaledFile) -> io::Result<(Self, u64)> {
+        let root_ptr = file.alloc()?;
+        Ok((Self::new(root_ptr), root_ptr))
     }
 
     pub fn ptr(&self) -> u64 {
         self.root_node_ptr
     }
 
-    fn get_node(&mut self, node_ptr: u64, file: &mut verter::File) -> Option<&mut KeyTreeNode> {
+    fn get_node(&mut self, node_ptr: u64, file: &mut JournaledFile) -> io::Result<&mut KeyTreeNode> {
         if !self.nodes.contains_key(&node_ptr) {
-            let node_data = file.read(node_ptr).ok()?;
+            let node_data = file.read(node_ptr)?;
             let node = KeyTreeNode::deserialize(&node_data);
             self.nodes.insert(node_ptr, node);
         }
-        self.nodes.get_mut(&node_ptr)
+        Ok(self.nodes.get_mut(&node_ptr).unwrap())
     }
 
-    fn set_node_child(&mut self, node_ptr: u64, child_byte: usize, new_child: u64, file: &mut verter::File) -> Option<bool> {
+    fn set_node_child(&mut self, node_ptr: u64, child_byte: usize, new_child: u64, file: &mut JournaledFile) -> io::Result<bool> {
         let root_node = self.root_node_ptr;
         let node = self.get_node(node_ptr, file)?;
 
@@ -104,21 +106,26 @@ impl Keymap {
         // If the node has no more children, delete it
         if node.n_children == 0 && node_ptr != root_node {
             self.nodes.remove(&node_ptr);
-            let _ = file.delete(node_ptr);
-            return Some(true);
+            file.delete(node_ptr)?;
+            return Ok(true);
         }       
 
-        node.save(file, node_ptr);
-        Some(false)
+        if let Err(error) = node.save(file, node_ptr) {
+            // Forget the cached node, so it's read back as it actually is in the file
+            self.nodes.remove(&node_ptr);
+            return Err(error);
+        }
+        Ok(false)
     }
 
-    fn get_ptr_at_node(&mut self, node_ptr: u64, path: &[u8], file: &mut verter::File) -> Option<u64> {
+    fn get_ptr_at_node(&mut self, node_ptr: u64, path: &[u8], file: &mut JournaledFile) -> io::Result<u64> {
         let node = self.get_node(node_ptr, file)?;
         let next = path[0] as usize;
 
         // Create an allocation if necessary
         if node.children[next] == 0 {
-            self.set_node_child(node_ptr, next, file.alloc().ok()?, file);
+            let child = file.alloc()?;
+            self.set_node_child(node_ptr, next, child, file)?;
         }
 
         let node = self.get_node(node_ptr, file)?;
@@ -126,7 +133,7 @@ impl Keymap {
 
         // We're at the leaf node of the tree, so we return the actual object pointer
         if path.len() == 1 {
-            return Some(child);
+            return Ok(child);
         }
 
         // Otherwise, go down a layer of the tree
@@ -135,33 +142,127 @@ impl Keymap {
 
     /// Get the pointer where an object is stored given the object's key.
     /// If the object does not yet have a place in the file, an allocation is made.
-    pub fn get_ptr(&mut self, key: u64, file: &mut verter::File) -> Option<u64> {
+    pub fn get_ptr(&mut self, key: u64, file: &mut JournaledFile) -> io::Result<u64> {
         if let Some(ptr) = self.map.get(&key) {
-            return Some(*ptr);
+            return Ok(*ptr);
         } 
 
         let path = key.to_be_bytes();
-        let ptr = self.get_ptr_at_node(self.root_node_ptr, path.as_slice(), file);
+        let ptr = self.get_ptr_at_node(self.root_node_ptr, path.as_slice(), file)?;
+        self.map.insert(key, ptr);
+        Ok(ptr)
+    }
+
+    fn find_ptr_at_node(&mut self, node_ptr: u64, path: &[u8], file: &mut JournaledFile) -> io::Result<Option<u64>> {
+        let node = self.get_node(node_ptr, file)?;
+        let child = node.children[path[0] as usize];
+        if child == 0 {
+            return Ok(None);
+        }
+        if path.len() == 1 {
+            return Ok(Some(child));
+        }
+        self.find_ptr_at_node(child, &path[1..], file)
+    }
+
+    /// Get the pointer where an object is stored given the object's key, without making an allocation if the object is not in the file.
+    pub fn find_ptr(&mut self, key: u64, file: &mut JournaledFile) -> io::Result<Option<u64>> {
+        if let Some(ptr) = self.map.get(&key) {
+            return Ok(Some(*ptr));
+        }
+
+        let path = key.to_be_bytes();
+        let ptr = self.find_ptr_at_node(self.root_node_ptr, path.as_slice(), file)?;
         if let Some(ptr) = ptr {
             self.map.insert(key, ptr);
         }
-        ptr
+        Ok(ptr)
+    }
+
+    /// Get the pointer to the node at the bottom of the tree that holds an object's entry
+    #[cfg(test)]
+    pub fn leaf_node_ptr(&mut self, key: u64, file: &mut JournaledFile) -> io::Result<Option<u64>> {
+        let mut node_ptr = self.root_node_ptr;
+        for byte in &key.to_be_bytes()[..7] {
+            let child = self.get_node(node_ptr, file)?.children[*byte as usize];
+            if child == 0 {
+                return Ok(None);
+            }
+            node_ptr = child;
+        }
+        Ok(Some(node_ptr))
+    }
+
+    fn collect_keys_at_node(&mut self, node_ptr: u64, depth: usize, prefix: u64, file: &mut JournaledFile, keys: &mut Vec<u64>) -> io::Result<()> {
+        let children = self.get_node(node_ptr, file)?.children;
+        for (byte, child) in children.into_iter().enumerate() {
+            if child == 0 {
+                continue;
+            }
+            let key = (prefix << 8) | byte as u64;
+            // We're at the leaf node of the tree, so the children are the actual object pointers
+            if depth == 7 {
+                keys.push(key);
+            } else {
+                self.collect_keys_at_node(child, depth + 1, key, file, keys)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the keys of all the objects stored in the file.
+    pub fn keys(&mut self, file: &mut JournaledFile) -> io::Result<Vec<u64>> {
+        let mut keys = Vec::new();
+        self.collect_keys_at_node(self.root_node_ptr, 0, 0, file, &mut keys)?;
+        Ok(keys)
+    }
+
+    fn check_at_node(&mut self, node_ptr: u64, depth: usize, prefix: u64, file: &mut JournaledFile, entries: &mut Vec<(u64, u64)>, corrupt_nodes: &mut Vec<u64>) {
+        // Nodes are lists of (byte, child pointer) pairs, so anything else is corrupt
+        let node_data = match file.read(node_ptr) {
+            Ok(node_data) if node_data.len() % 9 == 0 => node_data,
+            _ => {
+                corrupt_nodes.push(node_ptr);
+                return;
+            }
+        };
+        let node = KeyTreeNode::deserialize(&node_data);
+        for (byte, child) in node.children.into_iter().enumerate() {
+            if child == 0 {
+                continue;
+            }
+            let key = (prefix << 8) | byte as u64;
+            if depth == 7 {
+                entries.push((key, child));
+            } else {
+                self.check_at_node(child, depth + 1, key, file, entries, corrupt_nodes);
+            }
+        }
+    }
+
+    /// Walk the whole tree, reading every node from the file.
+    /// Returns every (key, pointer) entry that could be reached, along with the pointers of the nodes that could not be read.
+    pub fn check(&mut self, file: &mut JournaledFile) -> (Vec<(u64, u64)>, Vec<u64>) {
+        let mut entries = Vec::new();
+        let mut corrupt_nodes = Vec::new();
+        self.check_at_node(self.root_node_ptr, 0, 0, file, &mut entries, &mut corrupt_nodes);
+        (entries, corrupt_nodes)
     }
 
-    fn delete_at_node(&mut self, node_ptr: u64, path: &[u8], file: &mut verter::File) -> Option<bool> {
+    fn delete_at_node(&mut self, node_ptr: u64, path: &[u8], file: &mut JournaledFile) -> io::Result<bool> {
         let node = self.get_node(node_ptr, file)?;
         let next = path[0] as usize;
 
         // If the node has no children past this point, there's nothing to delete
         if node.children[next] == 0 {
-            return Some(false);
+            return Ok(false);
         }
 
         let child = node.children[next];
 
         // We're at the leaf node of the tree, so we delete the actual object data 
         if path.len() == 1 {
-            let _ = file.delete(child);
+            file.delete(child)?;
             return self.set_node_child(node_ptr, next, 0, file);
         }
 
@@ -173,14 +274,15 @@ impl Keymap {
             return self.set_node_child(node_ptr, next, 0, file);
         }
 
-        Some(false)
+        Ok(false)
     }
 
     /// Delete an object from the file given its key.
-    pub fn delete(&mut self, key: u64, file: &mut verter::File) {
+    pub fn delete(&mut self, key: u64, file: &mut JournaledFile) -> io::Result<()> {
         self.map.remove(&key);
         let path = key.to_be_bytes();
-        self.delete_at_node(self.root_node_ptr, path.as_slice(), file);
+        self.delete_at_node(self.root_node_ptr, path.as_slice(), file)?;
+        Ok(())
     }
 
 }
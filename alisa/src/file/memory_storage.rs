@@ -0,0 +1,172 @@
+
+use std::collections::HashMap;
+
+use crate::Error;
+
+use super::Storage;
+
+/// Keeps a project in memory. Nothing is written to the disk. See `Client::in_memory`.
+pub struct MemoryStorage {
+    project: Option<Vec<u8>>,
+    objects: HashMap<u64, Vec<u8>>,
+    curr_key: u64,
+    version: u64
+}
+
+impl MemoryStorage {
+
+    pub fn new() -> Self {
+        Self {
+            project: None,
+            objects: HashMap::new(),
+            curr_key: 1,
+            version: 0
+        }
+    }
+
+}
+
+impl Default for MemoryStorage {
+
+    fn default() -> Self {
+        Self::new()
+    }
+
+}
+
+impl Storage for MemoryStorage {
+
+    fn read_project(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.project.clone())
+    }
+
+    fn write_project(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.project = Some(data.to_vec());
+        Ok(())
+    }
+
+    fn read_object(&mut self, key: u64) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.objects.get(&key).cloned())
+    }
+
+    fn write_object(&mut self, _kind: &str, key: u64, data: &[u8]) -> Result<(), Error> {
+        self.objects.insert(key, data.to_vec());
+        Ok(())
+    }
+
+    fn delete_object(&mut self, key: u64) -> Result<(), Error> {
+        self.objects.remove(&key);
+        Ok(())
+    }
+
+    fn keys(&mut self) -> Result<Vec<u64>, Error> {
+        Ok(self.objects.keys().copied().collect())
+    }
+
+    fn curr_key(&self) -> u64 {
+        self.curr_key
+    }
+
+    fn set_curr_key(&mut self, curr_key: u64) -> Result<(), Error> {
+        self.curr_key = curr_key;
+        Ok(())
+    }
+
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) -> Result<(), Error> {
+        self.version = version;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_project::{create_folder, DeleteFolder, SetFolderName, SetN, TestProject}, Action, Client, Ptr, Server, UndoRedoManager};
+
+    #[test]
+    fn operations_can_be_undone() {
+        let mut client = Client::<TestProject>::in_memory();
+        let mut undo_redo = UndoRedoManager::new();
+
+        let mut action = Action::new();
+        client.perform(&mut action, SetN { n: 5 });
+        undo_redo.add(action);
+        let folder = create_folder(&client, Ptr::null(), "Folder");
+        client.tick(&mut ());
+        assert_eq!(client.project().n, 5);
+
+        let mut action = Action::new();
+        client.perform(&mut action, SetFolderName { ptr: folder, name_value: "Renamed".to_owned() });
+        client.perform(&mut action, DeleteFolder { ptr: folder });
+        undo_redo.add(action);
+        client.tick(&mut ());
+        assert!(client.get(folder).is_none());
+        assert_eq!(client.project().folders.iter().count(), 0);
+
+        // Undoing brings back the folder as it was before the action
+        undo_redo.undo(&client);
+        client.tick(&mut ());
+        assert_eq!(client.get(folder).unwrap().name, "Folder");
+        assert_eq!(client.project().folders.iter().collect::<Vec<_>>(), vec![folder]);
+
+        undo_redo.undo(&client);
+        client.tick(&mut ());
+        assert_eq!(client.project().n, 0);
+
+        undo_redo.redo(&client);
+        client.tick(&mut ());
+        assert_eq!(client.project().n, 5);
+    }
+
+    #[test]
+    fn unloaded_objects_are_loaded_from_memory() {
+        let mut client = Client::<TestProject>::in_memory();
+        let parent = create_folder(&client, Ptr::null(), "Parent");
+        let child = create_folder(&client, parent, "Child");
+        client.tick(&mut ());
+        assert!(child.key > parent.key);
+
+        client.unload(parent);
+        client.unload(child);
+        client.tick(&mut ());
+        assert!(client.objects.folders.is_empty());
+
+        // Loading the parent loads its children through their `LoadingPtr`s
+        client.request_load(parent);
+        client.tick(&mut ());
+        assert_eq!(client.get(parent).unwrap().name, "Parent");
+        assert_eq!(client.get(child).unwrap().name, "Child");
+        assert_eq!(client.get(child).unwrap().parent, parent);
+    }
+
+    #[test]
+    fn in_memory_server_shares_operations() {
+        let mut server = Server::<TestProject>::in_memory(());
+        let (sender_id, welcome) = server.add_client();
+        let mut sender = Client::<TestProject>::collab(welcome).unwrap();
+        let (receiver_id, welcome) = server.add_client();
+        let mut receiver = Client::<TestProject>::collab(welcome).unwrap();
+
+        sender.perform(&mut Action::new(), SetN { n: 3 });
+        sender.tick(&mut ());
+        for msg in sender.take_messages() {
+            server.receive_message(sender_id, msg).unwrap();
+        }
+        assert_eq!(server.project().n, 3);
+
+        for msg in std::mem::take(server.get_msgs_to_send(receiver_id).unwrap()) {
+            receiver.receive_message(msg, &mut ()).unwrap();
+        }
+        receiver.tick(&mut ());
+        assert_eq!(receiver.project().n, 3);
+    }
+
+}
@@ -1,130 +1,377 @@
-use std::path::Path;
+use std::{collections::{HashMap, HashSet}, io, path::Path};
 
-use keymap::Keymap;
-
-use crate::{rmpv_decode, rmpv_encode, rmpv_get, DeserializationContext, Project, SerializationContext};
+use crate::{migrate, rmpv_decode, rmpv_encode, DeserializationContext, DeserializationError, Error, GarbageReport, Project, SerializationContext};
 
 mod keymap;
 
+mod journal;
+use journal::JournaledFile;
+
+mod storage;
+pub use storage::*;
+
+mod verter_storage;
+pub use verter_storage::*;
+
+mod memory_storage;
+pub use memory_storage::*;
+
+mod directory_storage;
+pub use directory_storage::*;
+
+mod integrity;
+pub use integrity::*;
+
+/// The kind objects are written with when their kind isn't known. See `Storage::write_object`.
+pub(crate) const UNREACHABLE_KIND: &str = "unreachable";
+
 pub(crate) struct File {
-    /// The Verter file. Verter is used to allow O(1) incremental file reads/updates. For more info, see [Verter on crates.io](https://crates.io/crates/verter).
-    file: verter::File,
-    /// The pointer to the project data in the Verter file.
-    project_ptr: u64,
-    keymap: Keymap
+    /// Where the project data is kept. This is a `VerterStorage` unless the project was opened with a different storage.
+    storage: Box<dyn Storage>
 }
 
 impl File {
 
-    fn try_open(file: &mut verter::File) -> Option<(Keymap, u64, u64)> {
-
-        // Load file metadata
-        let root_data = file.read_root().ok()?;
-        let mut root_data = root_data.as_slice();
-        let root_data = rmpv::decode::read_value(&mut root_data).ok()?;
-        let curr_key = rmpv_get(&root_data, "curr_key")?.as_u64()?;
-        let project_ptr = rmpv_get(&root_data, "project_ptr")?.as_u64()?;
-        let keymap_ptr = rmpv_get(&root_data, "keymap_ptr")?.as_u64()?;
-
-        // Initialize the keymap
-        let keymap = Keymap::new(keymap_ptr); 
+    fn try_load_project<P: Project>(&mut self, project_data: &[u8], strict: bool) -> Result<(P, P::Objects, Vec<DeserializationError>), Error> {
+        let Some(mut project_data) = rmpv_decode(project_data) else {
+            return Err(Error::decode("could not decode project data"));
+        };
+        if self.is_migrating::<P>() {
+            migrate(P::MIGRATIONS, &mut project_data, self.version());
+        }
+        let mut objects = P::Objects::default();
+        let (project, errors) = {
+            let mut context = DeserializationContext::local(&mut objects, self).strict(strict);
+            let project = P::deserialize(&project_data, &mut context); 
+            (project, context.take_errors())
+        };
+        match project {
+            Some(project) if !strict || errors.is_empty() => Ok((project, objects, errors)),
+            _ => Err(errors.into_iter().next().map(Error::Decode).unwrap_or_else(|| Error::decode("invalid project data")))
+        }
+    }
 
-        Some((keymap, curr_key, project_ptr))
+    /// Open a project file or directory, returning the loaded project along with any problems encountered while deserializing it.
+    /// In strict mode, any problem in the project data makes opening the file fail.
+    pub fn open<P: Project, PathRef: AsRef<Path>>(path: PathRef, strict: bool) -> Result<(Self, P, P::Objects, u64, Vec<DeserializationError>), Error> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            return Self::open_storage(Box::new(DirectoryStorage::open(path)?), strict);
+        }
+        let storage = VerterStorage::open(path, P::verter_config())?;
+        Self::open_storage(Box::new(storage), strict)
     }
 
-    fn try_load_project<P: Project>(&mut self) -> Option<(P, P::Objects)> {
-        let project_data = self.read(self.project_ptr)?;
-        let mut objects = P::Objects::default();
-        let mut context = DeserializationContext::local(&mut objects, self);
-        let project = P::deserialize(&project_data, &mut context)?; 
-        Some((project, objects))
+    /// Open a project file or directory without writing anything to it.
+    /// A batch left in the journal is read as if it were saved, but not applied, and data from older versions of the project format is only migrated when read.
+    pub fn open_read_only<P: Project>(path: &Path) -> Result<Self, Error> {
+        let storage: Box<dyn Storage> = if path.is_dir() {
+            Box::new(DirectoryStorage::open(path)?)
+        } else {
+            match VerterStorage::open_existing(path, P::verter_config())? {
+                Some(storage) => Box::new(storage),
+                None => return Err(Error::CorruptRoot)
+            }
+        };
+        let file = Self::from_storage(storage);
+        if file.version() > P::VERSION {
+            return Err(Error::VersionMismatch { found: file.version(), supported: P::VERSION });
+        }
+        Ok(file)
     }
 
-    fn write_root(file: &mut verter::File, curr_key: u64, project_ptr: u64, keymap_ptr: u64) {
-        if let Some(data) = rmpv_encode(&rmpv::Value::Map(vec![
-            ("curr_key".into(), curr_key.into()),
-            ("project_ptr".into(), project_ptr.into()),
-            ("keymap_ptr".into(), keymap_ptr.into()),
-        ])) {
-            let _ = file.write_root(&data);
+    /// Wrap a storage without loading anything from it
+    pub fn from_storage(storage: Box<dyn Storage>) -> Self {
+        Self {
+            storage
         }
     }
 
-    pub fn open<P: Project, PathRef: AsRef<Path>>(path: PathRef) -> Option<(Self, P, P::Objects, u64)> {
+    /// Open a project kept in any storage. New storage is filled in with an empty project.
+    /// Data from an older version of the project format is always loaded strictly, since the migrated data is written back over it. See `repair_file`.
+    pub fn open_storage<P: Project>(storage: Box<dyn Storage>, strict: bool) -> Result<(Self, P, P::Objects, u64, Vec<DeserializationError>), Error> {
 
-        let mut file = verter::File::open(path, P::verter_config()).ok()?; // TODO: add configuration for magic bytes
+        let mut file = Self::from_storage(storage);
+
+        // Refuse to open files written by a newer version of the project format
+        if file.version() > P::VERSION {
+            return Err(Error::VersionMismatch { found: file.version(), supported: P::VERSION });
+        }
 
         // Load the project
 
-        let (keymap, curr_key, project_ptr) = if let Some((keymap, curr_key, project_ptr)) = Self::try_open(&mut file) {
-            (keymap, curr_key, project_ptr)
+        let (project, mut objects, warnings) = if let Some(project_data) = file.storage.read_project()? {
+            // Data that had to be skipped or defaulted can't be written back without losing the original
+            let strict = strict || file.is_migrating::<P>();
+            file.try_load_project(&project_data, strict)?
         } else {
-            let curr_key = 1;
-            let (keymap, keymap_ptr) = Keymap::create_empty(&mut file)?;
-            let project_ptr = file.alloc().ok()?; 
+            // New storage has nothing in it yet, so it's already up to date
+            file.storage.set_version(P::VERSION)?;
+
+            let project = P::empty();
+            let objects = P::Objects::default();
 
-            Self::write_root(&mut file, curr_key, project_ptr, keymap_ptr);
+            let project_data = project.serialize(&SerializationContext::shallow());
+            file.write_project(&project_data)?;
 
-            (keymap, curr_key, project_ptr)
+            (project, objects, Vec::new())
         };
 
-        let mut file = Self {
-            file,
-            project_ptr,
-            keymap,
+        // If the file was migrated, write back all the upgraded data so the file is fully up to date
+        if file.is_migrating::<P>() {
+            let project_data = project.serialize(&SerializationContext::shallow());
+            file.write_project(&project_data)?;
+            for object_kind in P::OBJECTS {
+                (object_kind.save_modifications)(&mut file, &mut objects)?;
+            }
+            file.set_version(P::VERSION)?;
+        }
+
+        file.commit()?;
+
+        let curr_key = file.storage.curr_key();
+        Ok((file, project, objects, curr_key, warnings)) 
+    }
+
+    /// Load the project along with every object reachable from it, including objects only referenced through plain `Ptr`s.
+    /// Returns `None` if the project data can't be deserialized, and the problems encountered while loading otherwise.
+    pub fn load_all<P: Project>(&mut self) -> Result<Option<(P, P::Objects, Vec<DeserializationError>)>, Error> {
+        let Some(mut project_data) = self.read_project()? else {
+            return Ok(None);
+        };
+        if self.is_migrating::<P>() {
+            migrate(P::MIGRATIONS, &mut project_data, self.version());
+        }
+        let mut objects = P::Objects::default();
+        let (project, errors) = {
+            let mut context = DeserializationContext::local(&mut objects, self).load_ptr_targets(true);
+            let project = P::deserialize(&project_data, &mut context);
+            (project, context.take_errors())
         };
+        Ok(project.map(|project| (project, objects, errors)))
+    }
 
-        if file.read_bytes(file.project_ptr).is_none() {
-            file.project_ptr = file.file.alloc().ok()?;
+    /// Find the keys of all the objects reachable from the project, following both `LoadingPtr`s and plain `Ptr`s.
+    /// Pointers to objects that aren't in the file are returned alongside, since nothing is reachable through them.
+    /// Fails if any of the data along the way can't be read, since the objects referenced by it can't be known.
+    pub fn reachable_keys<P: Project>(&mut self) -> Result<(HashSet<u64>, Vec<DeserializationError>), Error> {
+        let stored = self.keys()?.into_iter().collect::<HashSet<_>>();
+        let project_data = self.storage.read_project()?.unwrap_or_default();
+        let Some(mut project_data) = rmpv_decode(&project_data) else {
+            return Err(Error::decode("could not decode project data"));
+        };
+        if self.is_migrating::<P>() {
+            migrate(P::MIGRATIONS, &mut project_data, self.version());
+        }
+        let mut objects = P::Objects::default();
+        let (project, errors) = {
+            let mut context = DeserializationContext::local(&mut objects, self).load_ptr_targets(true);
+            let project = P::deserialize(&project_data, &mut context);
+            (project, context.take_errors())
+        };
+        let mut dangling = Vec::new();
+        for error in errors {
+            match error.unavailable_object() {
+                Some((_, key)) if !stored.contains(&key) => dangling.push(error),
+                _ => return Err(Error::Decode(error))
+            }
         }
+        if project.is_none() {
+            return Err(Error::decode("invalid project data"));
+        }
+        let reachable = P::OBJECTS.iter().flat_map(|object_kind| (object_kind.keys)(&objects)).collect();
+        Ok((reachable, dangling))
+    }
 
-        let (project, objects) = if let Some((projects, objects)) = file.try_load_project() {
-            (projects, objects)
-        } else {
-            let project = P::empty();
-            let objects = P::Objects::default();
+    /// Delete all the objects that can't be reached from the project
+    pub fn collect_garbage<P: Project>(&mut self) -> Result<GarbageReport, Error> {
+        let (reachable, dangling) = self.reachable_keys::<P>()?;
+        let mut removed = self.keys()?.into_iter().filter(|key| !reachable.contains(key)).collect::<Vec<_>>();
+        removed.sort();
+        for key in &removed {
+            self.delete(*key)?;
+        }
+        self.commit()?;
+        Ok(GarbageReport {
+            removed,
+            kept: reachable.len(),
+            dangling
+        })
+    }
 
-            let project_data = project.serialize(&SerializationContext::shallow());
-            file.write_project(&project_data);
+    /// Is the data in the file from an older version of the project format?
+    pub fn is_migrating<P: Project>(&self) -> bool {
+        self.version() < P::VERSION
+    }
+
+    pub fn version(&self) -> u64 {
+        self.storage.version()
+    }
+
+    /// The first key that was never handed out to an object
+    pub fn curr_key(&self) -> u64 {
+        self.storage.curr_key()
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.storage.path()
+    }
+
+    /// Set the version of the project format the data in the file is written with
+    pub fn set_version(&mut self, version: u64) -> Result<(), Error> {
+        self.storage.set_version(version)
+    }
+
+    /// Save the first key that was never handed out to an object
+    pub fn set_curr_key(&mut self, curr_key: u64) -> Result<(), Error> {
+        self.storage.set_curr_key(curr_key)
+    }
+
+    /// Read the project data. Returns `None` if no project data was written yet.
+    pub fn read_project(&mut self) -> Result<Option<rmpv::Value>, Error> {
+        let Some(data) = self.storage.read_project()? else {
+            return Ok(None);
+        };
+        match rmpv_decode(&data) {
+            Some(data) => Ok(Some(data)),
+            None => Err(Error::decode("could not decode project data"))
+        }
+    }
+
+    pub fn write_project(&mut self, data: &rmpv::Value) -> Result<(), Error> { 
+        let Some(data) = rmpv_encode(data) else {
+            return Err(Error::io(io::ErrorKind::InvalidData, "could not encode project data"));
+        };
+        self.storage.write_project(&data)
+    }
+
+    /// Read the encoded data of an object. Returns `None` if the object is not in the file.
+    pub fn read_bytes(&mut self, key: u64) -> Result<Option<Vec<u8>>, Error> {
+        self.storage.read_object(key)
+    }
 
-            (project, objects)
+    /// Read the data of an object. Returns `None` if the object is not in the file.
+    pub fn read(&mut self, key: u64) -> Result<Option<rmpv::Value>, Error> {
+        let Some(data) = self.storage.read_object(key)? else {
+            return Ok(None);
         };
+        match rmpv_decode(&data) {
+            Some(data) => Ok(Some(data)),
+            None => Err(Error::decode("could not decode object data"))
+        }
+    }
 
-        Some((file, project, objects, curr_key)) 
+    pub fn write(&mut self, kind: &str, key: u64, data: &rmpv::Value) -> Result<(), Error> {
+        let Some(data) = rmpv_encode(data) else {
+            return Err(Error::io(io::ErrorKind::InvalidData, "could not encode data"));
+        };
+        self.storage.write_object(kind, key, &data)
     }
 
-    pub fn read_bytes(&mut self, ptr: u64) -> Option<Vec<u8>> {
-        self.file.read(ptr).ok()
+    /// Get the keys of all the objects stored in the file
+    pub fn keys(&mut self) -> Result<Vec<u64>, Error> {
+        self.storage.keys()
     }
 
-    pub fn read(&mut self, ptr: u64) -> Option<rmpv::Value> {
-        rmpv_decode(&self.read_bytes(ptr)?)
+    pub fn delete(&mut self, key: u64) -> Result<(), Error> {
+        self.storage.delete_object(key)
     }
 
-    pub fn write_bytes(&mut self, ptr: u64, data: &[u8]) {
-        let _ = self.file.write(ptr, data);
+    /// Find the kind of every object reachable from the project, by key.
+    /// Also returns whether all of the reachable data could be read. If not, some objects might be reachable without having been found.
+    pub fn object_kinds<P: Project>(&mut self) -> Result<(HashMap<u64, &'static str>, bool), Error> {
+        let stored = self.keys()?.into_iter().collect::<HashSet<_>>();
+        let Some((_, objects, errors)) = self.load_all::<P>()? else {
+            return Ok((HashMap::new(), false));
+        };
+        // Pointers to objects that aren't in the file don't hide any objects
+        let complete = errors.iter().all(|error| error.unavailable_object().is_some_and(|(_, key)| !stored.contains(&key)));
+        let kinds = P::OBJECTS.iter().flat_map(|object_kind| (object_kind.keys)(&objects).into_iter().map(|key| (key, object_kind.name))).collect();
+        Ok((kinds, complete))
     }
 
-    pub fn write(&mut self, ptr: u64, data: &rmpv::Value) {
-        if let Some(data) = rmpv_encode(data) {
-            self.write_bytes(ptr, &data);
+    /// Copy the project data and the objects reachable from it into another file, preserving the objects' keys.
+    /// The data is copied as is. The project is only deserialized to find the kind of each object.
+    /// If some of the reachable data can't be read, the objects that couldn't be reached are copied too, since they might be reachable through it.
+    /// Returns the number of objects copied. Call `commit` on the target to save the copy.
+    pub fn copy_into<P: Project>(&mut self, target: &mut File, curr_key: u64) -> Result<usize, Error> {
+        let (kinds, complete) = self.object_kinds::<P>()?;
+        if let Some(project_data) = self.storage.read_project()? {
+            target.storage.write_project(&project_data)?;
+        }
+        let keys = self.keys()?.into_iter().filter(|key| !complete || kinds.contains_key(key)).collect::<Vec<_>>();
+        for key in &keys {
+            // A listed object that can't be found means the file is damaged, so the copy would be missing data
+            let Some(data) = self.storage.read_object(*key)? else {
+                return Err(Error::io(io::ErrorKind::NotFound, "object in the file could not be found"));
+            };
+            let kind = kinds.get(key).copied().unwrap_or(UNREACHABLE_KIND);
+            target.storage.write_object(kind, *key, &data)?;
         }
+        target.set_version(self.version())?;
+        target.set_curr_key(curr_key)?;
+        Ok(keys.len())
     }
 
-    pub fn write_project(&mut self, data: &rmpv::Value) { 
-        self.write(self.project_ptr, data);
+    /// Atomically save all the writes made since the last commit. Until then, nothing is written to the disk.
+    /// If the app stops while committing, the commit is finished the next time the file is opened.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        self.storage.commit()
     }
 
-    pub fn update_root(&mut self, curr_key: u64) {
-        Self::write_root(&mut self.file, curr_key, self.project_ptr, self.keymap.ptr());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_project::{create_folder, temp_path, Folder, TestProject}, Client, Error, File, Object, Project, Ptr, Storage, VerterStorage};
+
+    /// Write a project with a single folder, and make it look like it was written by the first version of the project format
+    fn write_old_project(path: &std::path::Path, folder_data: Option<&[u8]>) -> Result<Ptr<Folder>, Error> {
+        let mut client = Client::<TestProject>::local(path)?;
+        let folder = create_folder(&client, Ptr::null(), "Folder");
+        client.tick(&mut ());
+        drop(client);
+
+        let mut storage = VerterStorage::open(path, TestProject::verter_config())?;
+        if let Some(folder_data) = folder_data {
+            storage.write_object(Folder::NAME, folder.key, folder_data)?;
+        }
+        storage.set_version(0)?;
+        storage.commit()?;
+        Ok(folder)
     }
 
-    pub fn get_ptr(&mut self, key: u64) -> Option<u64> {
-        self.keymap.get_ptr(key, &mut self.file)
+    #[test]
+    fn older_files_are_migrated() -> Result<(), Error> {
+        let path = temp_path("migrated");
+        let folder = write_old_project(&path, None)?;
+
+        let mut client = Client::<TestProject>::local(&path)?;
+        client.request_load(folder);
+        client.tick(&mut ());
+        assert_eq!(client.get(folder).unwrap().name, "Folder");
+        drop(client);
+        assert_eq!(File::open_read_only::<TestProject>(&path)?.version(), TestProject::VERSION);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
     }
 
-    pub fn delete(&mut self, key: u64) {
-        self.keymap.delete(key, &mut self.file);
+    #[test]
+    fn older_files_with_unreadable_data_are_left_untouched() -> Result<(), Error> {
+        let path = temp_path("unmigratable");
+        // Valid MessagePack, but not a folder
+        let folder_data = [0xa3, b'a', b'b', b'c'];
+        let folder = write_old_project(&path, Some(&folder_data))?;
+
+        assert!(Client::<TestProject>::local(&path).is_err());
+        let mut file = File::open_read_only::<TestProject>(&path)?;
+        assert_eq!(file.version(), 0);
+        assert_eq!(file.read_bytes(folder.key)?, Some(folder_data.to_vec()));
+        drop(file);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
     }
 
 }
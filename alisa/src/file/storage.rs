@@ -0,0 +1,42 @@
+
+use std::path::Path;
+
+use crate::Error;
+
+/// Where the data of a local project is kept. Every object is stored under its key, as encoded MessagePack.
+/// Changes don't need to be saved until `commit` is called, which happens at the end of every tick in which something changed.
+///
+/// `VerterStorage`, a single Verter file, is used by `Client::local`. To keep a project somewhere else, implement this trait and use `Client::with_storage`.
+pub trait Storage {
+
+    /// Read the project's own data. Returns `None` if nothing was stored yet.
+    fn read_project(&mut self) -> Result<Option<Vec<u8>>, Error>;
+    fn write_project(&mut self, data: &[u8]) -> Result<(), Error>;
+
+    /// Read the data of an object. Returns `None` if there is no object stored under the key.
+    fn read_object(&mut self, key: u64) -> Result<Option<Vec<u8>>, Error>;
+    /// Write the data of an object. `kind` is the `Object::NAME` of the object, which storages can use to organize objects.
+    /// Objects whose kind isn't known, like objects that can't be reached from the project, are written with the kind `"unreachable"`.
+    fn write_object(&mut self, kind: &str, key: u64, data: &[u8]) -> Result<(), Error>;
+    /// Delete an object. Deleting an object that isn't stored does nothing.
+    fn delete_object(&mut self, key: u64) -> Result<(), Error>;
+    /// Get the keys of all the stored objects
+    fn keys(&mut self) -> Result<Vec<u64>, Error>;
+
+    /// The first key that was never handed out to an object. Keys start at 1.
+    fn curr_key(&self) -> u64;
+    fn set_curr_key(&mut self, curr_key: u64) -> Result<(), Error>;
+
+    /// The `Project::VERSION` the stored data was written with. 0 for new storage.
+    fn version(&self) -> u64;
+    fn set_version(&mut self, version: u64) -> Result<(), Error>;
+
+    /// Save all the changes since the last commit
+    fn commit(&mut self) -> Result<(), Error>;
+
+    /// The path of the file or directory the project is stored in, if any
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+
+}
@@ -0,0 +1,299 @@
+
+use std::{io::{self, Read}, path::{Path, PathBuf}};
+
+use crate::{rmpv_encode, rmpv_get, Error};
+
+use super::{keymap::Keymap, JournaledFile, Storage};
+
+/// Stores a project in a single Verter file. This is the storage used by `Client::local`.
+/// Verter is used to allow O(1) incremental file reads/updates. For more info, see [Verter on crates.io](https://crates.io/crates/verter).
+///
+/// Writes are held back until `commit`, which saves them atomically.
+pub struct VerterStorage {
+    file: JournaledFile,
+    path: PathBuf,
+    /// The pointer to the project data in the Verter file.
+    project_ptr: u64,
+    keymap: Keymap,
+    curr_key: u64,
+    version: u64,
+    /// Does the root data of the Verter file need to be updated?
+    root_modified: bool
+}
+
+/// Verter files start with the magic bytes, so we check them ourselves to tell a file of the wrong kind apart from a failed read
+fn check_magic_bytes(path: &Path, magic_bytes: &[u8]) -> Result<(), Error> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        // Files that don't exist yet are created when opened
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error.into())
+    };
+    let mut file_magic_bytes = Vec::new();
+    file.take(magic_bytes.len() as u64).read_to_end(&mut file_magic_bytes)?;
+    if !file_magic_bytes.is_empty() && file_magic_bytes[..] != magic_bytes[..] {
+        return Err(Error::BadMagic);
+    }
+    Ok(())
+}
+
+fn read_root(file: &mut JournaledFile) -> Option<(Keymap, u64, u64, u64)> {
+
+    // Load file metadata
+    let root_data = file.read_root().ok()?;
+    let mut root_data = root_data.as_slice();
+    let root_data = rmpv::decode::read_value(&mut root_data).ok()?;
+    let curr_key = rmpv_get(&root_data, "curr_key")?.as_u64()?;
+    let project_ptr = rmpv_get(&root_data, "project_ptr")?.as_u64()?;
+    let keymap_ptr = rmpv_get(&root_data, "keymap_ptr")?.as_u64()?;
+    // Files from before versioning was introduced don't store a version
+    let version = rmpv_get(&root_data, "version").and_then(rmpv::Value::as_u64).unwrap_or(0);
+
+    // Initialize the keymap
+    let keymap = Keymap::new(keymap_ptr); 
+
+    Some((keymap, curr_key, project_ptr, version))
+}
+
+impl VerterStorage {
+
+    /// Open a Verter project file, creating it if it doesn't exist
+    pub fn open<PathRef: AsRef<Path>>(path: PathRef, config: verter::Config) -> Result<Self, Error> {
+        let path = path.as_ref();
+        check_magic_bytes(path, config.magic_bytes)?;
+        // If the app stopped while saving, the rest of the save is picked up here and finished on the next commit
+        let mut file = JournaledFile::open(verter::File::open(path, config)?)?;
+
+        let (keymap, curr_key, project_ptr, version, root_modified) = if let Some((keymap, curr_key, project_ptr, version)) = read_root(&mut file) {
+            (keymap, curr_key, project_ptr, version, false)
+        } else if !file.read_root()?.is_empty() {
+            // Never replace a file we can't read with an empty project. See `check_file` and `repair_file`.
+            return Err(Error::CorruptRoot);
+        } else {
+            let (keymap, _) = Keymap::create_empty(&mut file)?;
+            let project_ptr = file.alloc()?; 
+            (keymap, 1, project_ptr, 0, true)
+        };
+
+        Ok(Self {
+            file,
+            path: path.to_owned(),
+            project_ptr,
+            keymap,
+            curr_key,
+            version,
+            root_modified
+        })
+    }
+
+    /// Open an existing Verter project file without writing anything to it. Returns `None` if the root data of the file can't be read.
+    pub(super) fn open_existing(path: &Path, config: verter::Config) -> Result<Option<Self>, Error> {
+        check_magic_bytes(path, config.magic_bytes)?;
+        let Ok(mut file) = JournaledFile::open(verter::File::open(path, config)?) else {
+            return Ok(None);
+        };
+        let Some((keymap, curr_key, project_ptr, version)) = read_root(&mut file) else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            file,
+            path: path.to_owned(),
+            project_ptr,
+            keymap,
+            curr_key,
+            version,
+            root_modified: false
+        }))
+    }
+
+    /// Walk the whole keymap. Returns every (key, pointer) entry that could be reached, along with the pointers of the nodes that could not be read.
+    pub(super) fn check_keymap(&mut self) -> (Vec<(u64, u64)>, Vec<u64>) {
+        self.keymap.check(&mut self.file)
+    }
+
+    pub(super) fn read_page(&mut self, ptr: u64) -> Option<Vec<u8>> {
+        self.file.read(ptr).ok()
+    }
+
+    /// Overwrite the keymap node holding an object's entry with data that isn't a valid node
+    #[cfg(test)]
+    pub(super) fn corrupt_keymap_node(&mut self, key: u64) -> Result<(), Error> {
+        let Some(node_ptr) = self.keymap.leaf_node_ptr(key, &mut self.file)? else {
+            return Err(Error::io(io::ErrorKind::NotFound, "object not in the keymap"));
+        };
+        self.file.write(node_ptr, b"corrupt")?;
+        self.file.commit()?;
+        Ok(())
+    }
+
+    fn write_root(&mut self) -> io::Result<()> {
+        let Some(data) = rmpv_encode(&rmpv::Value::Map(vec![
+            ("curr_key".into(), self.curr_key.into()),
+            ("project_ptr".into(), self.project_ptr.into()),
+            ("keymap_ptr".into(), self.keymap.ptr().into()),
+            ("version".into(), self.version.into()),
+        ])) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "could not encode file root data"));
+        };
+        self.file.write_root(&data)
+    }
+
+}
+
+impl Storage for VerterStorage {
+
+    fn read_project(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let project_data = self.file.read(self.project_ptr)?;
+        // A file that was created but never saved to has no project data yet
+        Ok((!project_data.is_empty()).then_some(project_data))
+    }
+
+    fn write_project(&mut self, data: &[u8]) -> Result<(), Error> {
+        Ok(self.file.write(self.project_ptr, data)?)
+    }
+
+    fn read_object(&mut self, key: u64) -> Result<Option<Vec<u8>>, Error> {
+        let Some(ptr) = self.keymap.find_ptr(key, &mut self.file)? else {
+            return Ok(None);
+        };
+        Ok(Some(self.file.read(ptr)?))
+    }
+
+    fn write_object(&mut self, _kind: &str, key: u64, data: &[u8]) -> Result<(), Error> {
+        let ptr = self.keymap.get_ptr(key, &mut self.file)?;
+        Ok(self.file.write(ptr, data)?)
+    }
+
+    fn delete_object(&mut self, key: u64) -> Result<(), Error> {
+        Ok(self.keymap.delete(key, &mut self.file)?)
+    }
+
+    fn keys(&mut self) -> Result<Vec<u64>, Error> {
+        Ok(self.keymap.keys(&mut self.file)?)
+    }
+
+    fn curr_key(&self) -> u64 {
+        self.curr_key
+    }
+
+    fn set_curr_key(&mut self, curr_key: u64) -> Result<(), Error> {
+        self.curr_key = curr_key;
+        self.root_modified = true;
+        Ok(())
+    }
+
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) -> Result<(), Error> {
+        self.version = version;
+        self.root_modified = true;
+        Ok(())
+    }
+
+    /// Atomically save all the writes made since the last commit. Until then, nothing is written to the disk.
+    /// If the app stops while committing, the commit is finished the next time the file is opened.
+    fn commit(&mut self) -> Result<(), Error> {
+        if self.root_modified {
+            self.write_root()?;
+            self.root_modified = false;
+        }
+        Ok(self.file.commit()?)
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_project::temp_path, Error, Storage};
+
+    use super::VerterStorage;
+
+    fn config() -> verter::Config {
+        verter::Config {
+            magic_bytes: b"ALISA___",
+            page_size: 64,
+        }
+    }
+
+    /// Save a project with two objects, then stage a batch that changes the project, replaces one object, deletes the other and adds a third
+    fn stage_batch(storage: &mut VerterStorage) -> Result<(), Error> {
+        storage.write_project(b"old project")?;
+        storage.write_object("Note", 1, b"old 1")?;
+        storage.write_object("Note", 2, b"old 2")?;
+        storage.set_curr_key(3)?;
+        storage.commit()?;
+
+        storage.write_project(b"new project")?;
+        storage.write_object("Note", 1, b"new 1")?;
+        storage.delete_object(2)?;
+        storage.write_object("Note", 3, b"new 3")?;
+        storage.set_curr_key(4)
+    }
+
+    fn assert_old_state(storage: &mut VerterStorage) -> Result<(), Error> {
+        assert_eq!(storage.read_project()?.as_deref(), Some(&b"old project"[..]));
+        assert_eq!(storage.read_object(1)?.as_deref(), Some(&b"old 1"[..]));
+        assert_eq!(storage.read_object(2)?.as_deref(), Some(&b"old 2"[..]));
+        assert_eq!(storage.read_object(3)?, None);
+        assert_eq!(storage.curr_key(), 3);
+        Ok(())
+    }
+
+    fn assert_new_state(storage: &mut VerterStorage) -> Result<(), Error> {
+        assert_eq!(storage.read_project()?.as_deref(), Some(&b"new project"[..]));
+        assert_eq!(storage.read_object(1)?.as_deref(), Some(&b"new 1"[..]));
+        assert_eq!(storage.read_object(2)?, None);
+        assert_eq!(storage.read_object(3)?.as_deref(), Some(&b"new 3"[..]));
+        let mut keys = storage.keys()?;
+        keys.sort();
+        assert_eq!(keys, vec![1, 3]);
+        assert_eq!(storage.curr_key(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn interrupted_commit_is_finished_when_reopened() -> Result<(), Error> {
+        let path = temp_path("interrupted_commit");
+        {
+            let mut storage = VerterStorage::open(&path, config())?;
+            stage_batch(&mut storage)?;
+            storage.write_root()?;
+            storage.file.commit_partially(1)?;
+        }
+
+        // The journaled batch is read as if it were fully applied
+        let mut storage = VerterStorage::open(&path, config())?;
+        assert_new_state(&mut storage)?;
+
+        // Committing applies the rest of the batch and removes the journal
+        storage.commit()?;
+        drop(storage);
+        let mut storage = VerterStorage::open(&path, config())?;
+        assert_new_state(&mut storage)?;
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn uncommitted_batch_is_discarded_when_reopened() -> Result<(), Error> {
+        let path = temp_path("uncommitted_batch");
+        {
+            let mut storage = VerterStorage::open(&path, config())?;
+            stage_batch(&mut storage)?;
+        }
+
+        let mut storage = VerterStorage::open(&path, config())?;
+        assert_old_state(&mut storage)?;
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+}
@@ -0,0 +1,26 @@
+
+use std::path::Path;
+
+use crate::{DeserializationError, Error, File, Project};
+
+/// The result of collecting the garbage in a project file
+#[derive(Clone, Debug, Default)]
+pub struct GarbageReport {
+    /// The keys of the unreachable objects that were deleted
+    pub removed: Vec<u64>,
+    /// The number of objects still in the file
+    pub kept: usize,
+    /// The pointers to objects that aren't in the file. Nothing can be reached through them, so they don't stop the garbage from being collected.
+    pub dangling: Vec<DeserializationError>
+}
+
+/// Delete all the objects in a project file that can't be reached from the project, following both `LoadingPtr`s and plain `Ptr`s.
+/// Run this before opening the file with `Client::local` to clean it up on open. Nothing is deleted if any of the reachable data can't be read,
+/// except for pointers to objects missing from the file, which are reported in `GarbageReport::dangling`.
+pub fn collect_garbage<P: Project, PathRef: AsRef<Path>>(path: PathRef) -> Result<GarbageReport, Error> {
+    if !path.as_ref().exists() {
+        return Err(Error::io(std::io::ErrorKind::NotFound, "file does not exist"));
+    }
+    let (mut file, _, _, _, _) = File::open::<P, _>(path, true)?;
+    file.collect_garbage::<P>()
+}
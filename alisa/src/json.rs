@@ -0,0 +1,323 @@
+
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::{rmpv_decode, Error, File, Project, UNREACHABLE_KIND};
+
+// MessagePack values that have no direct JSON equivalent are written as single-entry objects with one of these keys
+const BINARY_KEY: &str = "$binary";
+const EXT_KEY: &str = "$ext";
+const MAP_KEY: &str = "$map";
+const F32_KEY: &str = "$f32";
+const F64_KEY: &str = "$f64";
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|idx| u8::from_str_radix(hex.get(idx..(idx + 2))?, 16).ok()).collect()
+}
+
+/// JSON has no NaN or infinities, so they are written as strings
+fn float_to_json(value: f64) -> serde_json::Value {
+    if value.is_nan() {
+        "NaN".into()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf".into() } else { "-inf".into() }
+    } else {
+        value.into()
+    }
+}
+
+fn float_from_json(value: &serde_json::Value) -> Option<f64> {
+    match value.as_str() {
+        Some("NaN") => Some(f64::NAN),
+        Some("inf") => Some(f64::INFINITY),
+        Some("-inf") => Some(f64::NEG_INFINITY),
+        Some(_) => None,
+        None => value.as_f64()
+    }
+}
+
+/// Convert a MessagePack value to JSON.
+/// Binary data, extension values and maps with non-string keys are written as `{"$binary": "<hex>"}`, `{"$ext": [type, "<hex>"]}` and `{"$map": [[key, value], ...]}`.
+/// 32-bit floats are written as `{"$f32": value}` so they keep their precision, and 64-bit NaN and infinities as `{"$f64": "NaN"}`, `{"$f64": "inf"}` and `{"$f64": "-inf"}`.
+pub fn rmpv_to_json(value: &rmpv::Value) -> serde_json::Value {
+    match value {
+        rmpv::Value::Nil => serde_json::Value::Null,
+        rmpv::Value::Boolean(value) => (*value).into(),
+        rmpv::Value::Integer(value) => {
+            if let Some(value) = value.as_u64() {
+                value.into()
+            } else {
+                value.as_i64().unwrap_or_default().into()
+            }
+        },
+        rmpv::Value::F32(value) => json!({ F32_KEY: float_to_json(*value as f64) }),
+        rmpv::Value::F64(value) if value.is_finite() => (*value).into(),
+        rmpv::Value::F64(value) => json!({ F64_KEY: float_to_json(*value) }),
+        rmpv::Value::String(value) => match value.as_str() {
+            Some(value) => value.into(),
+            None => json!({ BINARY_KEY: hex_encode(value.as_bytes()) }),
+        },
+        rmpv::Value::Binary(bytes) => json!({ BINARY_KEY: hex_encode(bytes) }),
+        rmpv::Value::Array(values) => serde_json::Value::Array(values.iter().map(rmpv_to_json).collect()),
+        rmpv::Value::Map(entries) => {
+            // Maps with keys that aren't strings, or that could be confused with the special forms, are written as a list of entries
+            if entries.iter().all(|(key, _)| key.as_str().is_some_and(|key| !key.starts_with('$'))) {
+                serde_json::Value::Object(entries.iter().map(|(key, value)| (key.as_str().unwrap().to_owned(), rmpv_to_json(value))).collect())
+            } else {
+                json!({ MAP_KEY: entries.iter().map(|(key, value)| json!([rmpv_to_json(key), rmpv_to_json(value)])).collect::<Vec<_>>() })
+            }
+        },
+        rmpv::Value::Ext(ext_type, bytes) => json!({ EXT_KEY: [ext_type, hex_encode(bytes)] }),
+    }
+}
+
+/// Convert JSON written by `rmpv_to_json` back to a MessagePack value
+pub fn json_to_rmpv(value: &serde_json::Value) -> Option<rmpv::Value> {
+    Some(match value {
+        serde_json::Value::Null => rmpv::Value::Nil,
+        serde_json::Value::Bool(value) => (*value).into(),
+        serde_json::Value::Number(number) => {
+            if let Some(value) = number.as_u64() {
+                value.into()
+            } else if let Some(value) = number.as_i64() {
+                value.into()
+            } else {
+                number.as_f64()?.into()
+            }
+        },
+        serde_json::Value::String(value) => value.as_str().into(),
+        serde_json::Value::Array(values) => rmpv::Value::Array(values.iter().map(json_to_rmpv).collect::<Option<Vec<_>>>()?),
+        serde_json::Value::Object(entries) => {
+            if entries.len() == 1 {
+                let (key, value) = entries.iter().next()?;
+                match key.as_str() {
+                    BINARY_KEY => return Some(rmpv::Value::Binary(hex_decode(value.as_str()?)?)),
+                    EXT_KEY => {
+                        let [ext_type, bytes] = value.as_array()?.as_slice() else { return None; };
+                        return Some(rmpv::Value::Ext(ext_type.as_i64()?.try_into().ok()?, hex_decode(bytes.as_str()?)?));
+                    },
+                    F32_KEY => return Some(rmpv::Value::F32(float_from_json(value)? as f32)),
+                    F64_KEY => return Some(rmpv::Value::F64(float_from_json(value)?)),
+                    MAP_KEY => {
+                        let mut map = Vec::new();
+                        for entry in value.as_array()? {
+                            let [key, value] = entry.as_array()?.as_slice() else { return None; };
+                            map.push((json_to_rmpv(key)?, json_to_rmpv(value)?));
+                        }
+                        return Some(rmpv::Value::Map(map));
+                    },
+                    _ => {}
+                }
+            }
+            rmpv::Value::Map(entries.iter().map(|(key, value)| Some((key.as_str().into(), json_to_rmpv(value)?))).collect::<Option<Vec<_>>>()?)
+        },
+    })
+}
+
+fn json_error(message: String) -> Error {
+    Error::decode(&message)
+}
+
+/// Export a whole project file to a pretty-printed JSON document. The file is never modified.
+/// The document contains the project data and every object in the file as stored, grouped by object kind and keyed by object key.
+/// Objects in the file that can't be reached from the project are listed under `unreachable`, and objects that aren't valid MessagePack under `undecodable`.
+/// Data from older versions of the project format is exported without being migrated, and migrated when imported.
+pub fn export_json<P: Project, PathRef: AsRef<Path>>(path: PathRef) -> Result<String, Error> {
+    if !path.as_ref().exists() {
+        return Err(Error::io(std::io::ErrorKind::NotFound, "file does not exist"));
+    }
+    let mut file = File::open_read_only::<P>(path.as_ref())?;
+    let Some(project) = file.read_project()? else {
+        return Err(json_error("could not read project data".to_owned()));
+    };
+
+    // The project is only traversed to find the kind of each object. The exported data is read from the file as is.
+    let (kinds, _) = file.object_kinds::<P>()?;
+    let mut object_kinds = serde_json::Map::new();
+    for object_kind in P::OBJECTS {
+        object_kinds.insert(object_kind.name.to_owned(), serde_json::Value::Object(serde_json::Map::new()));
+    }
+    let mut unreachable = serde_json::Map::new();
+    let mut undecodable = serde_json::Map::new();
+    let mut keys = file.keys()?;
+    keys.sort();
+    for key in keys {
+        let Some(data) = file.read_bytes(key)? else { continue; };
+        let Some(data) = rmpv_decode(&data) else {
+            undecodable.insert(key.to_string(), rmpv_to_json(&rmpv::Value::Binary(data)));
+            continue;
+        };
+        let entries = match kinds.get(&key).and_then(|kind| object_kinds.get_mut(*kind)) {
+            Some(serde_json::Value::Object(entries)) => entries,
+            _ => &mut unreachable
+        };
+        entries.insert(key.to_string(), rmpv_to_json(&data));
+    }
+
+    let document = json!({
+        "version": file.version(),
+        "curr_key": file.curr_key(),
+        "project": rmpv_to_json(&project),
+        "objects": object_kinds,
+        "unreachable": unreachable,
+        "undecodable": undecodable
+    });
+    serde_json::to_string_pretty(&document).map_err(|error| json_error(error.to_string()))
+}
+
+/// Import a JSON document written by `export_json` into a new project file.
+/// Documents from older versions of the project format are migrated when the file is next opened. Objects listed under `undecodable` are not imported.
+pub fn import_json<P: Project, PathRef: AsRef<Path>>(json: &str, path: PathRef) -> Result<(), Error> {
+    let document: serde_json::Value = serde_json::from_str(json).map_err(|error| json_error(format!("invalid JSON: {}", error)))?;
+
+    let version = document.get("version").and_then(serde_json::Value::as_u64).unwrap_or(0);
+    if version > P::VERSION {
+        return Err(Error::VersionMismatch { found: version, supported: P::VERSION });
+    }
+    let Some(curr_key) = document.get("curr_key").and_then(serde_json::Value::as_u64) else {
+        return Err(json_error("expected `curr_key` in document".to_owned()));
+    };
+    let Some(project_data) = document.get("project").and_then(json_to_rmpv) else {
+        return Err(json_error("expected `project` in document".to_owned()));
+    };
+
+    // Convert all the objects before touching the file, so a bad document doesn't leave a half-written file behind
+    let mut groups = Vec::new();
+    if let Some(kinds) = document.get("objects") {
+        let Some(kinds) = kinds.as_object() else {
+            return Err(json_error("expected map of object kinds in `objects`".to_owned()));
+        };
+        for (kind_name, entries) in kinds {
+            if !P::OBJECTS.iter().any(|object_kind| object_kind.name == kind_name) {
+                return Err(json_error(format!("unknown object kind `{}`", kind_name)));
+            }
+            groups.push((kind_name.as_str(), entries));
+        }
+    }
+    if let Some(unreachable) = document.get("unreachable") {
+        groups.push((UNREACHABLE_KIND, unreachable));
+    }
+
+    let mut objects = Vec::new();
+    for (group_name, entries) in groups {
+        let Some(entries) = entries.as_object() else {
+            return Err(json_error(format!("expected map of objects for `{}`", group_name)));
+        };
+        for (key, data) in entries {
+            let Ok(key) = key.parse::<u64>() else {
+                return Err(json_error(format!("invalid object key `{}` in `{}`", key, group_name)));
+            };
+            let Some(data) = json_to_rmpv(data) else {
+                return Err(json_error(format!("invalid data for {}[{}]", group_name, key)));
+            };
+            objects.push((group_name, key, data));
+        }
+    }
+
+    if path.as_ref().exists() {
+        return Err(Error::io(std::io::ErrorKind::AlreadyExists, "file already exists"));
+    }
+    let (mut file, _, _, _, _) = File::open::<P, _>(path, false)?;
+    file.write_project(&project_data)?;
+    let mut next_key = curr_key;
+    for (kind, key, data) in objects {
+        file.write(kind, key, &data)?;
+        next_key = next_key.max(key + 1);
+    }
+    file.set_version(version)?;
+    file.set_curr_key(next_key)?;
+    file.commit()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rmpv_decode, rmpv_encode, test_project::{create_folder, temp_path, TestProject}, Client, File, Ptr};
+
+    use super::{export_json, import_json, json_to_rmpv, rmpv_to_json};
+
+    /// Convert a value to JSON text and back. NaN isn't equal to itself, so the values are compared by their encoding.
+    fn assert_round_trip(value: rmpv::Value) {
+        let json = serde_json::to_string(&rmpv_to_json(&value)).unwrap();
+        let converted = json_to_rmpv(&serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(rmpv_encode(&converted), rmpv_encode(&value), "{} was converted to {}", value, converted);
+    }
+
+    fn float_values() -> rmpv::Value {
+        rmpv::Value::Array(vec![
+            rmpv::Value::F32(f32::NAN),
+            rmpv::Value::F32(0.1),
+            rmpv::Value::F32(f32::NEG_INFINITY),
+            rmpv::Value::F64(f64::INFINITY),
+            rmpv::Value::F64(f64::NAN),
+            rmpv::Value::F64(0.1),
+            rmpv::Value::F64(2.0),
+        ])
+    }
+
+    #[test]
+    fn values_round_trip() {
+        assert_round_trip(rmpv::Value::Nil);
+        assert_round_trip(rmpv::Value::Boolean(true));
+        assert_round_trip(u64::MAX.into());
+        assert_round_trip((-5).into());
+        assert_round_trip("text".into());
+        assert_round_trip(rmpv::Value::Binary(vec![0, 1, 254, 255]));
+        assert_round_trip(rmpv::Value::Ext(5, vec![1, 2, 3]));
+        assert_round_trip(rmpv::Value::Ext(-1, Vec::new()));
+        assert_round_trip(rmpv::Value::Map(vec![
+            (1.into(), "integer key".into()),
+            (rmpv::Value::Array(vec![true.into()]), "array key".into()),
+        ]));
+        assert_round_trip(rmpv::Value::Map(vec![
+            ("$binary".into(), "looks like an escape".into()),
+        ]));
+        // JSON objects don't keep the order of their keys, so these are in sorted order
+        assert_round_trip(rmpv::Value::Map(vec![
+            ("children".into(), rmpv::Value::Array(vec![rmpv::Value::Map(vec![("x".into(), rmpv::Value::F32(1.5))])])),
+            ("name".into(), "Folder".into()),
+        ]));
+        assert_round_trip(float_values());
+    }
+
+    #[test]
+    fn export_and_import_preserve_data() {
+        let path = temp_path("export.alisa");
+        let imported_path = temp_path("import.alisa");
+        let folder = {
+            let mut client = Client::<TestProject>::local(&path).unwrap();
+            let folder = create_folder(&client, Ptr::null(), "Folder");
+            client.tick(&mut ());
+            folder
+        };
+        // An object no folder refers to, so its data is exported as stored
+        {
+            let (mut file, _, _, _, _) = File::open::<TestProject, _>(&path, false).unwrap();
+            file.write("Folder", 1000, &float_values()).unwrap();
+            file.commit().unwrap();
+        }
+
+        let json = export_json::<TestProject, _>(&path).unwrap();
+        import_json::<TestProject, _>(&json, &imported_path).unwrap();
+
+        let mut file = File::open_read_only::<TestProject>(&imported_path).unwrap();
+        let stored = file.read_bytes(1000).unwrap().unwrap();
+        assert_eq!(rmpv_encode(&rmpv_decode(&stored).unwrap()), rmpv_encode(&float_values()));
+        drop(file);
+        let client = Client::<TestProject>::local(&imported_path).unwrap();
+        assert_eq!(client.get(folder).unwrap().name, "Folder");
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&imported_path).unwrap();
+    }
+
+}
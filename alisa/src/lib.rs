@@ -19,17 +19,40 @@ pub use action::*;
 
 mod file;
 pub(crate) use file::*;
+pub use file::{check_file, repair_file, IntegrityIssue, IntegrityReport, Storage, VerterStorage, MemoryStorage, DirectoryStorage, DirectoryFormat};
 
 mod serialization;
 pub use serialization::*;
 
+mod error;
+pub use error::*;
+
 mod tree;
 pub use tree::*;
 
 mod util;
 pub use util::*;
 
+mod json;
+pub use json::*;
+
+mod gc;
+pub use gc::*;
+
+mod compact;
+pub use compact::*;
+
+mod convert;
+pub use convert::*;
+
+#[cfg(test)]
+extern crate self as alisa;
+#[cfg(test)]
+mod test_project;
+
 pub use verter;
 pub use alisa_proc_macros::*;
 pub use rmpv;
-pub use paste;
\ No newline at end of file
+pub use paste;
+#[cfg(feature = "serde")]
+pub use serde;
\ No newline at end of file
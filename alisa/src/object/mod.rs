@@ -1,5 +1,5 @@
 
-use crate::{DeleteObjectDelta, Project, Recorder, RecreateObjectDelta, Serializable};
+use crate::{DeleteObjectDelta, Migration, Project, Recorder, RecreateObjectDelta, Serializable};
 
 mod ptr;
 pub use ptr::*;
@@ -16,9 +16,18 @@ pub trait Object: Sized + Clone + Serializable<Self::Project> + 'static {
 
     const NAME: &'static str;
 
+    /// The migrations for this kind of object's data. See `Project::VERSION`.
+    const MIGRATIONS: &'static [Migration] = &[];
+
     fn list(objects: &<Self::Project as Project>::Objects) -> &ObjList<Self>;
     fn list_mut(objects: &mut <Self::Project as Project>::Objects) -> &mut ObjList<Self>;
 
+    /// An estimate of the memory used by the object in bytes, used to decide when to evict objects. See `Client::set_memory_budget`.
+    /// Objects that own heap data (strings, lists, images) should override this to include it.
+    fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+    }
+
     fn add(recorder: &mut Recorder<Self::Project>, ptr: Ptr<Self>, obj: Self) {
         recorder.obj_list_mut().insert(ptr, obj);
         recorder.push_delta(DeleteObjectDelta {
@@ -35,4 +44,13 @@ pub trait Object: Sized + Clone + Serializable<Self::Project> + 'static {
         }
     }
 
+    /// Delete all the loaded objects for which `keep` returns false.
+    /// Tree objects should be deleted through their tree operations instead, so they're removed from their parents and their children are deleted too.
+    fn retain<F: FnMut(Ptr<Self>, &Self) -> bool>(recorder: &mut Recorder<Self::Project>, mut keep: F) {
+        let to_delete = recorder.obj_list::<Self>().iter().filter(|(ptr, obj)| !keep(*ptr, obj)).map(|(ptr, _)| ptr).collect::<Vec<_>>();
+        for ptr in to_delete {
+            Self::delete(recorder, ptr);
+        }
+    }
+
 }
\ No newline at end of file
@@ -1,18 +1,58 @@
 
-use std::{any::{type_name, TypeId}, collections::HashSet};
+use std::{any::{type_name, TypeId}, collections::{HashMap, HashSet}};
 
-use crate::{DeserializationContext, File, LoadingPtr, Project, Serializable, SerializationContext};
+use crate::{DeleteObjectDelta, DeserializationContext, DeserializationError, Error, File, KindChanges, LoadingPtr, Project, Recorder, Serializable, SerializationContext};
 
 use super::{Object, Ptr};
 
 
 pub struct ObjectKind<P: Project> {
     pub(crate) name: &'static str,
-    pub(crate) save_modifications: fn(&mut File, objects: &mut P::Objects),
-    pub(crate) load_objects: fn(&mut File, &mut P::Objects),
-    pub(crate) load_object: fn(&mut File, &mut P::Objects, u64),
-    pub(crate) load_object_from_message: fn(&mut P::Objects, u64, &rmpv::Value),
-    pub(crate) serialize_object: fn(&mut P::Objects, u64) -> Option<rmpv::Value>,
+    /// Save the modified objects of this kind and delete the deleted ones.
+    /// Objects that could not be saved stay marked as modified, so saving them is retried next time.
+    pub(crate) save_modifications: fn(&mut File, objects: &mut P::Objects) -> Result<(), Error>,
+    /// Write the modified objects of this kind to a file and delete the deleted ones, without marking them as saved
+    pub(crate) write_modifications: fn(&mut File, &P::Objects) -> Result<(), Error>,
+    pub(crate) load_objects: fn(&mut File, &mut P::Objects) -> Vec<DeserializationError>,
+    /// Load an object, and the subtree of objects below it up to the given depth if given
+    pub(crate) load_object: fn(&mut File, &mut P::Objects, u64, Option<u32>) -> Vec<DeserializationError>,
+    pub(crate) load_object_from_message: fn(&mut P::Objects, u64, &rmpv::Value, Option<u32>) -> Vec<DeserializationError>,
+    /// Serialize an object along with the objects it loads, up to the given depth if given
+    pub(crate) serialize_object: fn(&mut P::Objects, u64, Option<u32>) -> Option<rmpv::Value>,
+    /// Serialize an object into a message shared with other objects, so that objects included in several of them are only encoded once
+    pub(crate) serialize_object_with: for<'a> fn(&'a P::Objects, u64, &SerializationContext<'a, P>) -> Option<rmpv::Value>,
+    /// Get the keys of all the loaded objects of this kind
+    pub(crate) keys: fn(&P::Objects) -> Vec<u64>,
+    /// Serialize all the loaded objects of this kind, along with their keys
+    pub(crate) serialize_all: fn(&P::Objects) -> Vec<(u64, rmpv::Value)>,
+    /// Is the object with the given key loaded?
+    pub(crate) contains: fn(&P::Objects, u64) -> bool,
+    /// Take the changes to the objects of this kind since the last tick
+    pub(crate) take_changes: fn(&mut P::Objects) -> KindChanges,
+    /// Start keeping the state objects of this kind were in before they change, so changes that end up undone aren't reported
+    pub(crate) snapshot_changes: fn(&mut P::Objects),
+    /// Stop reporting changes to objects of this kind that are back in the state they were in when `snapshot_changes` was called
+    pub(crate) forget_undone_changes: fn(&mut P::Objects),
+    /// Forget which objects need to be saved. Used by clients that don't save to a file.
+    pub(crate) clear_modifications: fn(&mut P::Objects),
+    /// Unload the objects of this kind that were requested to be unloaded, returning their keys
+    pub(crate) unload_requested: fn(&mut P::Objects) -> Vec<u64>,
+    /// Record the objects of this kind accessed since the last tick as accessed in the given tick
+    pub(crate) record_accesses: fn(&P::Objects, u64),
+    /// Get the estimated memory usage of every loaded object of this kind. Objects that were never accessed are considered accessed in the given tick.
+    pub(crate) memory_usage: fn(&P::Objects, u64) -> Vec<ObjectMemoryUsage>,
+    /// Get the keys of the objects an object loads through its `LoadingPtr`s
+    pub(crate) references: fn(&P::Objects, u64) -> HashSet<u64>,
+    /// Unload an object of this kind to free memory. It is reloaded when next accessed through the client.
+    pub(crate) evict: fn(&mut P::Objects, u64) -> bool,
+    /// Forget that an object of this kind was evicted, returning whether it was. Used when reloading it.
+    pub(crate) unevict: fn(&P::Objects, u64) -> bool,
+    /// Queue an object of this kind to be loaded from the file on the next tick
+    pub(crate) queue_load: fn(&P::Objects, u64),
+    /// Remove an object of this kind from memory after it was deleted from the file by collecting garbage
+    pub(crate) forget: fn(&mut P::Objects, u64),
+    /// Move the objects of this kind from a side buffer into the project, like the objects included in an operation's data
+    pub(crate) instance_objects: fn(&mut P::Objects, &mut Recorder<'_, P>),
 
     #[cfg(debug_assertions)]
     pub(crate) type_id: fn() -> TypeId,
@@ -20,11 +60,21 @@ pub struct ObjectKind<P: Project> {
     pub(crate) type_name: fn() -> &'static str
 }
 
-fn load_object<O: Object>(file: &mut File, objects: &mut <O::Project as Project>::Objects, key: u64) {
+/// The estimated memory used by a loaded object
+pub(crate) struct ObjectMemoryUsage {
+    pub(crate) key: u64,
+    /// The tick in which the object was last accessed
+    pub(crate) last_access: u64,
+    pub(crate) size: usize
+}
+
+fn load_object<O: Object>(file: &mut File, objects: &mut <O::Project as Project>::Objects, key: u64, subtree_depth: Option<u32>) -> Vec<DeserializationError> {
     // Fun trick: instead of implementing loading logic, just deserialize a LoadingPtr pointing to the object we want :)
     let loading_ptr = LoadingPtr::<O>::new(Ptr::from_key(key));
     let loading_ptr_data = loading_ptr.serialize(&SerializationContext::shallow());
-    LoadingPtr::<O>::deserialize(&loading_ptr_data, &mut DeserializationContext::local(objects, file));
+    let mut context = DeserializationContext::local(objects, file).subtree_depth(subtree_depth);
+    LoadingPtr::<O>::deserialize(&loading_ptr_data, &mut context);
+    context.take_errors()
 }
 
 impl<P: Project> ObjectKind<P> {
@@ -33,34 +83,159 @@ impl<P: Project> ObjectKind<P> {
         Self {
             name: O::NAME,
             save_modifications: |file, objects| {
-                for modified in std::mem::replace(&mut O::list_mut(objects).modified, HashSet::new()) {
-                    if let Some(object) = O::list(objects).get(modified) {
+                let mut result = Ok(());
+                for modified in std::mem::replace(O::list_mut(objects).modified.get_mut(), HashSet::new()) {
+                    if let Some(object) = O::list(objects).get_untracked(modified) {
                         let object_data = object.serialize(&SerializationContext::shallow());
-                        if let Some(ptr) = file.get_ptr(modified.key) {
-                            file.write(ptr, &object_data);
+                        if let Err(error) = file.write(O::NAME, modified.key, &object_data) {
+                            O::list_mut(objects).modified.get_mut().insert(modified);
+                            result = result.and(Err(error));
                         }
                     }
                 }
                 for deleted in std::mem::replace(&mut O::list_mut(objects).to_delete, HashSet::new()) {
-                    file.delete(deleted.key);
+                    if let Err(error) = file.delete(deleted.key) {
+                        O::list_mut(objects).to_delete.insert(deleted);
+                        result = result.and(Err(error));
+                    }
+                }
+                result
+            },
+            write_modifications: |file, objects| {
+                let list = O::list(objects);
+                for modified in list.modified.borrow().iter() {
+                    if let Some(object) = list.get_untracked(*modified) {
+                        file.write(O::NAME, modified.key, &object.serialize(&SerializationContext::shallow()))?;
+                    }
                 }
+                for deleted in &list.to_delete {
+                    file.delete(deleted.key)?;
+                }
+                Ok(())
             },
             load_objects: |file, objects| {
-                let to_load = std::mem::replace(&mut *O::list_mut(objects).to_load.borrow_mut(), HashSet::new());
-                for ptr in to_load {
-                    load_object::<O>(file, objects, ptr.key);
+                let to_load = std::mem::replace(O::list_mut(objects).to_load.get_mut(), HashMap::new());
+                let mut errors = Vec::new();
+                for (ptr, subtree_depth) in to_load {
+                    errors.extend(load_object::<O>(file, objects, ptr.key, subtree_depth));
                 }
+                errors
             },
-            load_object: |file, objects, key| {
-                load_object::<O>(file, objects, key);
+            load_object: |file, objects, key, subtree_depth| {
+                load_object::<O>(file, objects, key, subtree_depth)
             },
-            load_object_from_message: |objects, key, data| {
-                if let Some(obj) = O::deserialize(data, &mut DeserializationContext::collab(objects)) {
+            load_object_from_message: |objects, key, data, subtree_depth| {
+                let (obj, errors) = {
+                    let mut context = DeserializationContext::collab(objects).subtree_depth(subtree_depth);
+                    let obj = context.deserialize_object::<O>(O::NAME, key, data);
+                    (obj, context.take_errors())
+                };
+                if let Some(obj) = obj {
                     O::list_mut(objects).insert(Ptr::from_key(key), obj);
                 }
+                errors
+            },
+            serialize_object: |objects, key, max_depth| {
+                O::list(objects).get_untracked(Ptr::from_key(key)).map(|data| data.serialize(&SerializationContext::deep(objects).with_stored(key).with_max_depth(max_depth)))
+            },
+            serialize_object_with: |objects, key, context| {
+                O::list(objects).get_untracked(Ptr::from_key(key)).map(|data| data.serialize(context))
+            },
+            keys: |objects| {
+                O::list(objects).keys().map(|ptr| ptr.key).collect()
+            },
+            serialize_all: |objects| {
+                O::list(objects).iter().map(|(ptr, object)| (ptr.key, object.serialize(&SerializationContext::shallow()))).collect()
+            },
+            contains: |objects, key| {
+                O::list(objects).contains(Ptr::from_key(key))
+            },
+            take_changes: |objects| {
+                std::mem::take(O::list_mut(objects).changes.get_mut())
+            },
+            snapshot_changes: |objects| {
+                O::list_mut(objects).snapshot_changes();
+            },
+            forget_undone_changes: |objects| {
+                O::list_mut(objects).forget_undone_changes();
+            },
+            clear_modifications: |objects| {
+                let list = O::list_mut(objects);
+                list.modified.get_mut().clear();
+                list.to_delete.clear();
             },
-            serialize_object: |objects, key| {
-                O::list(objects).get(Ptr::from_key(key)).map(|data| data.serialize(&SerializationContext::deep(objects).with_stored(key)))
+            unload_requested: |objects| {
+                let list = O::list_mut(objects);
+                let to_unload = std::mem::replace(list.to_unload.get_mut(), HashSet::new());
+                let mut unloaded = Vec::new();
+                for ptr in to_unload {
+                    // Modifications that couldn't be saved only exist in memory, so the object is unloaded once they are saved
+                    if list.modified.get_mut().contains(&ptr) {
+                        list.to_unload.get_mut().insert(ptr);
+                        continue;
+                    }
+                    if list.unload(ptr).is_some() {
+                        unloaded.push(ptr.key);
+                    }
+                }
+                unloaded
+            },
+            record_accesses: |objects, tick| {
+                let list = O::list(objects);
+                let mut accessed = list.accessed.borrow_mut();
+                for ptr in list.touched.borrow_mut().drain() {
+                    accessed.insert(ptr, tick);
+                }
+            },
+            memory_usage: |objects, tick| {
+                let list = O::list(objects);
+                let mut accessed = list.accessed.borrow_mut();
+                list.iter().map(|(ptr, object)| ObjectMemoryUsage {
+                    key: ptr.key,
+                    last_access: *accessed.entry(ptr).or_insert(tick),
+                    size: object.memory_size()
+                }).collect()
+            },
+            references: |objects, key| {
+                let Some(object) = O::list(objects).get_untracked(Ptr::from_key(key)) else {
+                    return HashSet::new();
+                };
+                // Only the objects directly below are stored
+                let context = SerializationContext::deep(objects).with_stored(key).with_max_depth(Some(1));
+                object.serialize(&context);
+                let mut references = context.into_stored();
+                references.remove(&key);
+                references
+            },
+            evict: |objects, key| {
+                let list = O::list_mut(objects);
+                let ptr = Ptr::from_key(key);
+                // Never evict the only copy of modifications that couldn't be saved
+                if list.modified.get_mut().contains(&ptr) || list.unload(ptr).is_none() {
+                    return false;
+                }
+                list.evicted.get_mut().insert(ptr);
+                true
+            },
+            unevict: |objects, key| {
+                O::list(objects).evicted.borrow_mut().remove(&Ptr::from_key(key))
+            },
+            queue_load: |objects, key| {
+                O::list(objects).to_load.borrow_mut().insert(Ptr::from_key(key), None);
+            },
+            forget: |objects, key| {
+                O::list_mut(objects).forget(Ptr::from_key(key));
+            },
+            instance_objects: |objects, recorder| {
+                for (ptr, object) in O::list_mut(objects).drain() {
+                    if recorder.obj_list::<O>().contains(ptr) {
+                        continue;
+                    }
+                    recorder.obj_list_mut().insert(ptr, object);
+                    recorder.push_delta(DeleteObjectDelta {
+                        ptr
+                    });
+                }
             },
             #[cfg(debug_assertions)]
             type_id: || TypeId::of::<O>(),
@@ -1,15 +1,26 @@
-use std::{any::{type_name, TypeId}, cell::RefCell, collections::{HashMap, HashSet}};
+use std::{any::{type_name, TypeId}, cell::RefCell, collections::{HashMap, HashSet}, ops::{Deref, DerefMut}};
 
-use crate::Project;
+use crate::{KindChanges, Project, SerializationContext};
 
 use super::{Ptr, Object};
 
 
 pub struct ObjList<Obj: Object> {
     objs: HashMap<Ptr<Obj>, Obj>,
-    pub(crate) modified: HashSet<Ptr<Obj>>,
+    pub(crate) modified: RefCell<HashSet<Ptr<Obj>>>,
     pub(crate) to_delete: HashSet<Ptr<Obj>>,
-    pub(crate) to_load: RefCell<HashSet<Ptr<Obj>>>,
+    /// The objects to load on the next tick, along with the depth of the subtree to load below each of them
+    pub(crate) to_load: RefCell<HashMap<Ptr<Obj>, Option<u32>>>,
+    /// The objects that should be unloaded from memory on the next tick
+    pub(crate) to_unload: RefCell<HashSet<Ptr<Obj>>>,
+    /// The objects that were evicted to stay within the memory budget, and should be reloaded when accessed
+    pub(crate) evicted: RefCell<HashSet<Ptr<Obj>>>,
+    /// The tick in which each object was last accessed, used to evict the least recently used objects
+    pub(crate) accessed: RefCell<HashMap<Ptr<Obj>, u64>>,
+    /// The objects accessed since the last tick. Objects accessed in the current tick are never evicted.
+    pub(crate) touched: RefCell<HashSet<Ptr<Obj>>>,
+    /// The changes since the last tick, reported by `Client::tick`
+    pub(crate) changes: RefCell<KindChanges>,
 }
 
 impl<Obj: Object> ObjList<Obj> {
@@ -18,25 +29,143 @@ impl<Obj: Object> ObjList<Obj> {
         if self.objs.contains_key(&ptr) {
             return;
         }
+        self.snapshot(ptr);
         self.objs.insert(ptr, obj);
-        self.modified.insert(ptr);
+        self.evicted.get_mut().remove(&ptr);
+        self.modified.get_mut().insert(ptr);
+        self.changes.get_mut().record_created(ptr.key);
     }
 
     pub fn delete(&mut self, ptr: Ptr<Obj>) -> Option<Obj> {
         if self.get(ptr).is_none() {
             return None;
         }
+        self.snapshot(ptr);
         self.to_delete.insert(ptr);
+        self.accessed.get_mut().remove(&ptr);
+        self.touched.get_mut().remove(&ptr);
+        self.changes.get_mut().record_deleted(ptr.key);
         self.objs.remove(&ptr)
     }
 
     pub fn get(&self, ptr: Ptr<Obj>) -> Option<&Obj> {
-        self.objs.get(&ptr) 
+        let obj = self.objs.get(&ptr)?;
+        self.touched.borrow_mut().insert(ptr);
+        Some(obj)
     }
 
     pub fn get_mut(&mut self, ptr: Ptr<Obj>) -> Option<&mut Obj> {
-        self.modified.insert(ptr);
-        self.objs.get_mut(&ptr) 
+        self.modified.get_mut().insert(ptr);
+        self.snapshot(ptr);
+        let obj = self.objs.get_mut(&ptr)?;
+        self.touched.get_mut().insert(ptr);
+        self.changes.get_mut().record_modified(ptr.key);
+        Some(obj)
+    }
+
+    /// Get an object without counting it as accessed, so saving or sending objects doesn't keep them from being evicted
+    pub(crate) fn get_untracked(&self, ptr: Ptr<Obj>) -> Option<&Obj> {
+        self.objs.get(&ptr)
+    }
+
+    /// Is the object loaded?
+    pub fn contains(&self, ptr: Ptr<Obj>) -> bool {
+        self.objs.contains_key(&ptr)
+    }
+
+    /// The number of loaded objects
+    pub fn len(&self) -> usize {
+        self.objs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objs.is_empty()
+    }
+
+    /// Iterate over the pointers to all the loaded objects
+    pub fn keys(&self) -> impl Iterator<Item = Ptr<Obj>> + '_ {
+        self.objs.keys().copied()
+    }
+
+    /// Iterate over all the loaded objects. Iterating doesn't count as accessing the objects when deciding which ones to evict.
+    pub fn iter(&self) -> impl Iterator<Item = (Ptr<Obj>, &Obj)> {
+        self.objs.iter().map(|(ptr, obj)| (*ptr, obj))
+    }
+
+    /// Iterate mutably over all the loaded objects. An object is only marked as modified if it is actually mutated through the returned `ObjMut`.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Ptr<Obj>, ObjMut<'_, Obj>)> {
+        let modified = &self.modified;
+        let touched = &self.touched;
+        let changes = &self.changes;
+        self.objs.iter_mut().map(move |(ptr, obj)| (*ptr, ObjMut {
+            ptr: *ptr,
+            obj,
+            modified,
+            touched,
+            changes
+        }))
+    }
+
+    /// Delete all the loaded objects for which `keep` returns false.
+    /// Like `delete`, this doesn't record any deltas, so the deletions can't be undone or rolled back. Use `Object::retain` in operations.
+    pub fn retain<F: FnMut(Ptr<Obj>, &Obj) -> bool>(&mut self, mut keep: F) {
+        let to_delete = self.iter().filter(|(ptr, obj)| !keep(*ptr, obj)).map(|(ptr, _)| ptr).collect::<Vec<_>>();
+        for ptr in to_delete {
+            self.delete(ptr);
+        }
+    }
+
+    /// Remove an object from memory without deleting it
+    pub(crate) fn unload(&mut self, ptr: Ptr<Obj>) -> Option<Obj> {
+        let obj = self.objs.remove(&ptr)?;
+        self.modified.get_mut().remove(&ptr);
+        self.accessed.get_mut().remove(&ptr);
+        self.touched.get_mut().remove(&ptr);
+        self.changes.get_mut().record_unloaded(ptr.key);
+        Some(obj)
+    }
+
+    /// Remove an object that was already deleted from the file, without deleting it again
+    pub(crate) fn forget(&mut self, ptr: Ptr<Obj>) {
+        self.to_load.get_mut().remove(&ptr);
+        self.to_unload.get_mut().remove(&ptr);
+        self.evicted.get_mut().remove(&ptr);
+        if self.objs.remove(&ptr).is_some() {
+            self.modified.get_mut().remove(&ptr);
+            self.accessed.get_mut().remove(&ptr);
+            self.touched.get_mut().remove(&ptr);
+            self.changes.get_mut().record_deleted(ptr.key);
+        }
+    }
+
+    /// Keep the state of an object from before it changes, if changes are being snapshotted
+    fn snapshot(&mut self, ptr: Ptr<Obj>) {
+        let changes = self.changes.get_mut();
+        if changes.needs_snapshot(ptr.key) {
+            changes.snapshot(ptr.key, self.objs.get(&ptr).map(|obj| obj.serialize(&SerializationContext::shallow())));
+        }
+    }
+
+    /// Start keeping the state objects were in before they change. See `forget_undone_changes`.
+    pub(crate) fn snapshot_changes(&mut self) {
+        self.changes.get_mut().start_snapshots();
+    }
+
+    /// Stop reporting changes to the objects that are back in the state they were in when `snapshot_changes` was called
+    pub(crate) fn forget_undone_changes(&mut self) {
+        let snapshots = self.changes.get_mut().take_snapshots();
+        for (key, (data, change)) in snapshots {
+            let curr_data = self.objs.get(&Ptr::from_key(key)).map(|obj| obj.serialize(&SerializationContext::shallow()));
+            if curr_data == data {
+                self.changes.get_mut().restore(key, change);
+            }
+        }
+    }
+
+    /// Remove all the objects from the list, without marking them as deleted
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = (Ptr<Obj>, Obj)> + '_ {
+        self.modified.get_mut().clear();
+        self.objs.drain()
     }
 
 }
@@ -54,10 +183,47 @@ impl<O: Object> Default for ObjList<O> {
 
         Self {
             objs: HashMap::new(),
-            modified: HashSet::new(),
+            modified: RefCell::new(HashSet::new()),
             to_delete: HashSet::new(),
-            to_load: RefCell::new(HashSet::new()),
+            to_load: RefCell::new(HashMap::new()),
+            to_unload: RefCell::new(HashSet::new()),
+            evicted: RefCell::new(HashSet::new()),
+            accessed: RefCell::new(HashMap::new()),
+            touched: RefCell::new(HashSet::new()),
+            changes: RefCell::new(KindChanges::default()),
         }
     }
 
-}
\ No newline at end of file
+}
+
+/// A mutable reference to an object in an `ObjList`, returned by `ObjList::iter_mut`. Marks the object as modified when it is mutated.
+pub struct ObjMut<'a, Obj: Object> {
+    ptr: Ptr<Obj>,
+    obj: &'a mut Obj,
+    modified: &'a RefCell<HashSet<Ptr<Obj>>>,
+    touched: &'a RefCell<HashSet<Ptr<Obj>>>,
+    changes: &'a RefCell<KindChanges>
+}
+
+impl<Obj: Object> Deref for ObjMut<'_, Obj> {
+    type Target = Obj;
+
+    fn deref(&self) -> &Obj {
+        self.obj
+    }
+}
+
+impl<Obj: Object> DerefMut for ObjMut<'_, Obj> {
+
+    fn deref_mut(&mut self) -> &mut Obj {
+        self.modified.borrow_mut().insert(self.ptr);
+        self.touched.borrow_mut().insert(self.ptr);
+        let mut changes = self.changes.borrow_mut();
+        if changes.needs_snapshot(self.ptr.key) {
+            changes.snapshot(self.ptr.key, Some(self.obj.serialize(&SerializationContext::shallow())));
+        }
+        changes.record_modified(self.ptr.key);
+        self.obj
+    }
+
+}
@@ -18,6 +18,9 @@ pub trait Operation: Sized + Any + Serializable<Self::Project> {
 
     /// The name of the operation, used for collab messages. MAKE SURE THIS IS UNIQUE FOR ALL OPERATIONS!
     const NAME: &'static str;
+    /// Should the objects referenced through `LoadingPtr`s in the operation be sent along with it when collaborating?
+    /// Only enable this for operations that create the objects they reference, since every referenced object is sent with each operation.
+    const INCLUDE_OBJECTS: bool = false;
 
     /// Perform the operation.
     fn perform(&self, recorder: &mut Recorder<'_, Self::Project>); 
@@ -33,7 +36,7 @@ pub(crate) trait OperationDyn {
     fn perform(&self, recorder: &mut Recorder<'_, Self::Project>);
     fn inverse(&self, context: &ProjectContext<Self::Project>) -> Option<Box<dyn OperationDyn<Project = Self::Project>>>;
     fn name(&self) -> &'static str;
-    fn serialize(&self) -> rmpv::Value;
+    fn serialize(&self, objects: &<Self::Project as Project>::Objects) -> rmpv::Value;
 }
 
 impl<O: Operation + Serializable<O::Project>> OperationDyn for O {
@@ -54,8 +57,12 @@ impl<O: Operation + Serializable<O::Project>> OperationDyn for O {
         Self::NAME
     }
 
-    fn serialize(&self) -> rmpv::Value {
-        self.serialize(&SerializationContext::shallow())
+    fn serialize(&self, objects: &<Self::Project as Project>::Objects) -> rmpv::Value {
+        if Self::INCLUDE_OBJECTS {
+            self.serialize(&SerializationContext::deep(objects))
+        } else {
+            self.serialize(&SerializationContext::shallow())
+        }
     }
 
 }
@@ -63,7 +70,8 @@ impl<O: Operation + Serializable<O::Project>> OperationDyn for O {
 /// A kind of operation, stored as a struct in `Project::OPERATIONS`.
 pub struct OperationKind<P: Project> {
     pub(crate) name: &'static str,
-    pub(crate) deserialize: fn(&rmpv::Value) -> Option<Box<dyn Any>>,
+    /// Deserialize the operation, along with the objects included in its data
+    pub(crate) deserialize: fn(&rmpv::Value) -> Option<(Box<dyn Any>, P::Objects)>,
     pub(crate) perform: fn(Box<dyn Any>, &mut Recorder<'_, P>),
 
     #[cfg(debug_assertions)]
@@ -78,7 +86,9 @@ impl<P: Project> OperationKind<P> {
         Self {
             name: O::NAME,
             deserialize: |data| {
-                Some(Box::new(O::deserialize(data, &mut DeserializationContext::data())?))
+                let mut context = DeserializationContext::data();
+                let operation = O::deserialize(data, &mut context)?;
+                Some((Box::new(operation), context.take_data_objects()))
             },
             perform: |operation, recorder| {
                 let Ok(operation) = operation.downcast::<O>() else { return; };
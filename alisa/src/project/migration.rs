@@ -0,0 +1,30 @@
+
+/// A step in upgrading data stored by an older version of the project format.
+/// Migrations run over the raw MessagePack data of the project or an object before it is deserialized.
+pub struct Migration {
+    /// The `Project::VERSION` this migration upgrades the data to
+    pub version: u64,
+    /// Upgrade the data from the previous version
+    pub migrate: fn(&mut rmpv::Value)
+}
+
+impl Migration {
+
+    pub const fn new(version: u64, migrate: fn(&mut rmpv::Value)) -> Self {
+        Self {
+            version,
+            migrate
+        }
+    }
+
+}
+
+/// Run all the migrations needed to bring data stored at version `from_version` up to date.
+/// Migrations are run in the order they are listed.
+pub(crate) fn migrate(migrations: &[Migration], data: &mut rmpv::Value, from_version: u64) {
+    for migration in migrations {
+        if migration.version > from_version {
+            (migration.migrate)(data);
+        }
+    }
+}
@@ -2,6 +2,9 @@
 mod project_context;
 pub use project_context::*;
 
+mod migration;
+pub use migration::*;
+
 use crate::{Serializable, ObjectKind, OperationKind};
 
 pub trait Project: Sized + Serializable<Self> + 'static {
@@ -16,6 +19,12 @@ pub trait Project: Sized + Serializable<Self> + 'static {
     const OBJECTS: &'static [ObjectKind<Self>];
     const OPERATIONS: &'static [OperationKind<Self>];
 
+    /// The version of the project format. Increment this when making a change to the stored data that needs a `Migration`.
+    /// Files written by a newer version are refused.
+    const VERSION: u64 = 0;
+    /// The migrations for the project's own data
+    const MIGRATIONS: &'static [Migration] = &[];
+
     fn verter_config() -> verter::Config {
         verter::Config {
             magic_bytes: b"ALISA___",
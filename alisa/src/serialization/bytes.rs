@@ -0,0 +1,70 @@
+
+use std::ops::{Deref, DerefMut};
+
+use crate::Project;
+use super::{DeserializationContext, Serializable, SerializationContext};
+
+/// A byte buffer that is serialized as MessagePack binary data instead of an array of integers.
+#[derive(Clone, Default, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Bytes {
+
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.0
+    }
+
+}
+
+impl Deref for Bytes {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Bytes {
+
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+
+}
+
+impl From<Vec<u8>> for Bytes {
+
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+}
+
+impl From<Bytes> for Vec<u8> {
+
+    fn from(bytes: Bytes) -> Self {
+        bytes.0
+    }
+
+}
+
+impl<P: Project> Serializable<P> for Bytes {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        match data {
+            rmpv::Value::Binary(bytes) => Some(Self(bytes.clone())),
+            // Accept data written by a plain `Vec<u8>` field
+            rmpv::Value::Array(_) => Vec::<u8>::deserialize(data, context).map(Self),
+            _ => context.expected("binary", data)
+        }
+    }
+
+    fn serialize(&self, _context: &SerializationContext<P>) -> rmpv::Value {
+        rmpv::Value::Binary(self.0.clone())
+    }
+
+}
@@ -0,0 +1,110 @@
+use std::fmt::{Debug, Display};
+
+/// A step along the path to a value being deserialized
+#[derive(Clone)]
+pub(crate) enum PathSegment {
+    /// An object stored under its own key, like `Slide[42]`
+    Object(&'static str, u64),
+    /// A named field, like `.title`
+    Field(&'static str),
+    /// An element of a list, like `[3]`
+    Index(usize),
+    /// An entry of a map, like `["name"]`
+    Key(String)
+}
+
+pub(crate) fn format_path(path: &[PathSegment]) -> String {
+    let mut result = String::new();
+    for segment in path {
+        match segment {
+            PathSegment::Object(name, key) => result.push_str(&format!("{}[{}]", name, key)),
+            PathSegment::Field(name) => {
+                if !result.is_empty() {
+                    result.push('.');
+                }
+                result.push_str(name);
+            },
+            PathSegment::Index(idx) => result.push_str(&format!("[{}]", idx)),
+            PathSegment::Key(key) => result.push_str(&format!("[{}]", key)),
+        }
+    }
+    result
+}
+
+/// Get a human-readable name for the type of a MessagePack value, used in error messages
+pub fn rmpv_type_name(value: &rmpv::Value) -> &'static str {
+    match value {
+        rmpv::Value::Nil => "nil",
+        rmpv::Value::Boolean(_) => "bool",
+        rmpv::Value::Integer(_) => "int",
+        rmpv::Value::F32(_) | rmpv::Value::F64(_) => "float",
+        rmpv::Value::String(_) => "string",
+        rmpv::Value::Binary(_) => "binary",
+        rmpv::Value::Array(_) => "array",
+        rmpv::Value::Map(_) => "map",
+        rmpv::Value::Ext(_, _) => "ext",
+    }
+}
+
+/// A problem encountered while deserializing a value, along with where in the data it happened.
+/// For example, `Slide[42].text_boxes[3].title: expected string, got int`.
+#[derive(Clone)]
+pub struct DeserializationError {
+    path: String,
+    message: String,
+    /// The kind and key of the referenced object that could not be read, if that is the problem
+    unavailable_object: Option<(&'static str, u64)>
+}
+
+impl DeserializationError {
+
+    pub(crate) fn new(path: &[PathSegment], message: String) -> Self {
+        Self {
+            path: format_path(path),
+            message,
+            unavailable_object: None
+        }
+    }
+
+    pub(crate) fn with_unavailable_object(mut self, object: &'static str, key: u64) -> Self {
+        self.unavailable_object = Some((object, key));
+        self
+    }
+
+    /// The path to the value that could not be deserialized. Empty if the problem is with the root value.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// If the problem is that a referenced object could not be read, the name of the object's kind and its key
+    pub fn unavailable_object(&self) -> Option<(&'static str, u64)> {
+        self.unavailable_object
+    }
+
+}
+
+impl Display for DeserializationError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+
+}
+
+impl Debug for DeserializationError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+
+}
+
+impl std::error::Error for DeserializationError {}
@@ -1,12 +1,21 @@
 use std::hash::Hash;
 
-use crate::{Object, Ptr};
+use crate::{migrate, Object, Ptr};
 
 use super::{DeserializationContext, DeserializationContextKind, Serializable, SerializationContext, SerializationContextKind};
 
 const ALREADY_ENCODED_MSGPACK_EXT_CODE: i8 = 123;
 const ALREADY_ENCODED_MSGPACK_EXT_DATA: &'static [u8] = b"ENCODED";
 
+/// The value used in place of an object's data when the object is encoded elsewhere in the same message
+pub(crate) fn already_encoded() -> rmpv::Value {
+    rmpv::Value::Ext(ALREADY_ENCODED_MSGPACK_EXT_CODE, ALREADY_ENCODED_MSGPACK_EXT_DATA.into())
+}
+
+pub(crate) fn is_already_encoded(data: &rmpv::Value) -> bool {
+    matches!(data.as_ext(), Some((ALREADY_ENCODED_MSGPACK_EXT_CODE, ALREADY_ENCODED_MSGPACK_EXT_DATA)))
+}
+
 /// A reference to an object that indicates that the object refered to should be loaded from disk/the server when the referer is loaded. 
 pub struct LoadingPtr<O: Object> {
     ptr: Ptr<O>
@@ -27,13 +36,15 @@ impl<O: Object> LoadingPtr<O> {
     fn load_from_key_and_data(key: u64, object_data: &rmpv::Value, context: &mut DeserializationContext<O::Project>) -> Option<Self> {
         let ptr = Ptr::from_key(key);
         if !matches!(object_data.as_ext(), Some((ALREADY_ENCODED_MSGPACK_EXT_CODE, _))) {
-            let object = O::deserialize(&object_data, context)?;
+            let object = context.deserialize_object::<O>(O::NAME, key, object_data)?;
             match &mut context.kind {
                 DeserializationContextKind::Local { file: _, objects } | 
                 DeserializationContextKind::Collab { objects } => {
                     O::list_mut(objects).insert(ptr, object);
                 },
-                DeserializationContextKind::Data => unreachable!(),
+                DeserializationContextKind::Data { objects } => {
+                    O::list_mut(objects).insert(ptr, object);
+                },
             }
         }
         Some(LoadingPtr { ptr })
@@ -44,37 +55,57 @@ impl<O: Object> LoadingPtr<O> {
 impl<O: Object> Serializable<O::Project> for LoadingPtr<O> {
 
     fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<O::Project>) -> Option<Self> {
+        let within_subtree_depth = context.within_subtree_depth();
+        let loads_subtree = context.loads_subtree();
         match &mut context.kind {
             DeserializationContextKind::Local { file, objects } => {
-                let key = data.as_u64()?;
+                let Some(key) = data.as_u64() else {
+                    return context.expected("object key", data);
+                };
                 let ptr = Ptr::from_key(key);
 
-                // If the object is already loaded, skip loading it
-                if O::list(objects).get(ptr).is_some() || context.loaded.contains(&key) {
+                // If the object is already loaded or below the subtree being loaded, skip loading it.
+                // Loaded objects in a subtree are still walked, since their children might not be loaded.
+                if !within_subtree_depth || (O::list(objects).contains(ptr) && !loads_subtree) || context.loaded.contains(&key) {
                     return Some(Self {
                         ptr
                     });
                 }
                 context.loaded.insert(key);
 
-                let file_ptr = file.get_ptr(key)?;
-                let object_data = file.read(file_ptr)?; 
+                let mut object_data = match file.read(key) {
+                    Ok(Some(object_data)) => object_data,
+                    Ok(None) => {
+                        context.object_unavailable(O::NAME, key, format!("could not read {}[{}] from file", O::NAME, key));
+                        return None;
+                    },
+                    Err(error) => {
+                        context.object_unavailable(O::NAME, key, format!("could not read {}[{}] from file: {}", O::NAME, key, error));
+                        return None;
+                    }
+                };
+                if file.is_migrating::<O::Project>() {
+                    migrate(O::MIGRATIONS, &mut object_data, file.version());
+                }
 
                 Self::load_from_key_and_data(key, &object_data, context)
             },
             DeserializationContextKind::Collab { objects } => {
-                let data = data.as_array()?;
-                let key = data.get(0)?.as_u64()?;
+                let Some([key, object_data]) = data.as_array().map(Vec::as_slice) else {
+                    return context.expected("[key, object data] pair", data);
+                };
+                let Some(key) = key.as_u64() else {
+                    return context.expected("object key", key);
+                };
                 let ptr = Ptr::from_key(key);
-                let object_data = data.get(1)?;
 
-                // If the object data is encoded elsewhere in the message, just return the pointer
-                if object_data.is_ext() && object_data.as_ext()?.0 == ALREADY_ENCODED_MSGPACK_EXT_CODE && object_data.as_ext()?.1 == ALREADY_ENCODED_MSGPACK_EXT_DATA {
+                // If the object data is encoded elsewhere in the message or wasn't included, just return the pointer
+                if object_data.is_nil() || (object_data.is_ext() && object_data.as_ext()?.0 == ALREADY_ENCODED_MSGPACK_EXT_CODE && object_data.as_ext()?.1 == ALREADY_ENCODED_MSGPACK_EXT_DATA) {
                     return Some(LoadingPtr { ptr })
                 } 
 
                 // If the object is already loaded, skip loading it
-                if O::list(objects).get(ptr).is_some() || context.loaded.contains(&key) {
+                if (O::list(objects).contains(ptr) && !loads_subtree) || context.loaded.contains(&key) {
                     return Some(Self {
                         ptr
                     });
@@ -83,8 +114,36 @@ impl<O: Object> Serializable<O::Project> for LoadingPtr<O> {
 
                 Self::load_from_key_and_data(key, object_data, context)
             },
-            DeserializationContextKind::Data => {
-                todo!()
+            DeserializationContextKind::Data { objects } => {
+                // Shallowly-serialized pointers are just the key of the object
+                if let Some(key) = data.as_u64() {
+                    return Some(Self {
+                        ptr: Ptr::from_key(key)
+                    });
+                }
+
+                let Some([key, object_data]) = data.as_array().map(Vec::as_slice) else {
+                    return context.expected("object key or [key, object data] pair", data);
+                };
+                let Some(key) = key.as_u64() else {
+                    return context.expected("object key", key);
+                };
+                let ptr = Ptr::from_key(key);
+
+                // If the object data is encoded elsewhere in the data or wasn't available to the sender, just return the pointer
+                if object_data.is_nil() || (object_data.is_ext() && object_data.as_ext()?.0 == ALREADY_ENCODED_MSGPACK_EXT_CODE && object_data.as_ext()?.1 == ALREADY_ENCODED_MSGPACK_EXT_DATA) {
+                    return Some(LoadingPtr { ptr })
+                }
+
+                // If the object is already in the buffer, skip loading it
+                if O::list(objects).contains(ptr) || context.loaded.contains(&key) {
+                    return Some(Self {
+                        ptr
+                    });
+                }
+                context.loaded.insert(key);
+
+                Self::load_from_key_and_data(key, object_data, context)
             }
         }
     }
@@ -102,9 +161,20 @@ impl<O: Object> Serializable<O::Project> for LoadingPtr<O> {
                         rmpv::Value::Ext(ALREADY_ENCODED_MSGPACK_EXT_CODE, ALREADY_ENCODED_MSGPACK_EXT_DATA.into())
                     ]);
                 } 
+
+                // Leave out objects below the requested depth
+                let depth = context.depth.get() + 1;
+                if context.max_depth.is_some_and(|max_depth| depth > max_depth) {
+                    return rmpv::Value::Array(vec![
+                        self.ptr.key.into(),
+                        rmpv::Value::Nil
+                    ]);
+                }
                 context.stored.borrow_mut().insert(self.ptr.key);
 
-                let obj_data = O::list(objects).get(self.ptr).map(|obj| obj.serialize(context)).unwrap_or(rmpv::Value::Nil);
+                context.depth.set(depth);
+                let obj_data = O::list(objects).get_untracked(self.ptr).map(|obj| obj.serialize(context)).unwrap_or(rmpv::Value::Nil);
+                context.depth.set(depth - 1);
                 rmpv::Value::Array(vec![
                     self.ptr.key.into(),
                     obj_data
@@ -1,13 +1,24 @@
 
-use std::{cell::RefCell, collections::HashSet};
+use std::{cell::{Cell, RefCell}, collections::HashSet};
 
 use crate::{File, Project};
 
 mod serialization_impls;
 
+mod error;
+pub use error::*;
+
 mod loading_ptr;
 pub use loading_ptr::*;
 
+mod bytes;
+pub use bytes::*;
+
+#[cfg(feature = "serde")]
+mod serde_value;
+#[cfg(feature = "serde")]
+pub use serde_value::*;
+
 enum DeserializationContextKind<'a, P: Project> {
     Local {
         file: &'a mut File,
@@ -16,13 +27,28 @@ enum DeserializationContextKind<'a, P: Project> {
     Collab {
         objects: &'a mut P::Objects,
     },
-    Data
+    /// Deserializing plain data, like an operation. Objects included in the data are put into a side buffer.
+    Data {
+        objects: P::Objects
+    }
 }
 
 pub struct DeserializationContext<'a, P: Project> {
     kind: DeserializationContextKind<'a, P>,
     /// The keys of the objects already loaded
     loaded: HashSet<u64>,
+    /// The path to the value currently being deserialized
+    path: Vec<PathSegment>,
+    /// The problems encountered so far
+    errors: Vec<DeserializationError>,
+    /// In strict mode, a value containing any errors fails to deserialize instead of falling back to defaults
+    strict: bool,
+    /// Also load the objects pointed to by plain `Ptr`s, not just `LoadingPtr`s
+    load_ptr_targets: bool,
+    /// When loading a subtree, the number of levels of objects below the root object to load
+    subtree_depth: Option<u32>,
+    /// The number of objects the value currently being deserialized is nested in
+    depth: u32
 }
 
 impl<'a, P: Project> DeserializationContext<'a, P> {
@@ -34,6 +60,12 @@ impl<'a, P: Project> DeserializationContext<'a, P> {
                 objects
             },
             loaded: HashSet::new(),
+            path: Vec::new(),
+            errors: Vec::new(),
+            strict: false,
+            load_ptr_targets: false,
+            subtree_depth: None,
+            depth: 0
         }
     }
 
@@ -42,14 +74,146 @@ impl<'a, P: Project> DeserializationContext<'a, P> {
             kind: DeserializationContextKind::Collab {
                 objects,
             },
-            loaded: HashSet::new()
+            loaded: HashSet::new(),
+            path: Vec::new(),
+            errors: Vec::new(),
+            strict: false,
+            load_ptr_targets: false,
+            subtree_depth: None,
+            depth: 0
         }
     }
 
     pub(crate) fn data() -> Self {
         Self {
-            kind: DeserializationContextKind::Data,
+            kind: DeserializationContextKind::Data {
+                objects: P::Objects::default()
+            },
             loaded: HashSet::new(),
+            path: Vec::new(),
+            errors: Vec::new(),
+            strict: false,
+            load_ptr_targets: false,
+            subtree_depth: None,
+            depth: 0
+        }
+    }
+
+    pub(crate) fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub(crate) fn load_ptr_targets(mut self, load_ptr_targets: bool) -> Self {
+        self.load_ptr_targets = load_ptr_targets;
+        self
+    }
+
+    pub(crate) fn subtree_depth(mut self, subtree_depth: Option<u32>) -> Self {
+        self.subtree_depth = subtree_depth;
+        self
+    }
+
+    /// Are we loading a subtree? If so, objects that are already loaded are still walked to load their missing descendants.
+    pub(crate) fn loads_subtree(&self) -> bool {
+        self.subtree_depth.is_some()
+    }
+
+    /// Are the objects pointed to at the current depth part of the subtree being loaded?
+    pub(crate) fn within_subtree_depth(&self) -> bool {
+        self.subtree_depth.map(|max_depth| self.depth <= max_depth).unwrap_or(true)
+    }
+
+    /// Should the objects pointed to by plain `Ptr`s be loaded too? This is needed when migrating a file, so that every object gets migrated.
+    pub(crate) fn loads_ptr_targets(&self) -> bool {
+        self.load_ptr_targets || self.is_migrating()
+    }
+
+    /// Record a problem with the value currently being deserialized
+    pub fn error<S: Into<String>>(&mut self, message: S) {
+        self.errors.push(DeserializationError::new(&self.path, message.into()));
+    }
+
+    /// Record that an object referenced by the value currently being deserialized could not be read
+    pub(crate) fn object_unavailable(&mut self, object: &'static str, key: u64, message: String) {
+        self.errors.push(DeserializationError::new(&self.path, message).with_unavailable_object(object, key));
+    }
+
+    /// Record that the value currently being deserialized has the wrong type. Always returns `None` for convenience.
+    pub fn expected<T>(&mut self, expected: &str, data: &rmpv::Value) -> Option<T> {
+        self.error(format!("expected {}, got {}", expected, rmpv_type_name(data)));
+        None
+    }
+
+    /// The problems encountered so far
+    pub fn errors(&self) -> &[DeserializationError] {
+        &self.errors
+    }
+
+    pub(crate) fn take_errors(&mut self) -> Vec<DeserializationError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn deserialize_at<T: Serializable<P>>(&mut self, segment: PathSegment, data: &rmpv::Value) -> Option<T> {
+        self.path.push(segment);
+        let n_errors = self.errors.len();
+        let mut result = T::deserialize(data, self);
+        if result.is_none() && self.errors.len() == n_errors {
+            self.error(format!("invalid {}", rmpv_type_name(data)));
+        }
+        // In strict mode, any problem inside the value makes the whole value fail
+        if self.strict && self.errors.len() > n_errors {
+            result = None;
+        }
+        self.path.pop();
+        result
+    }
+
+    /// Deserialize the value of a named field, tracking the field in the path for error messages
+    pub fn deserialize_field<T: Serializable<P>>(&mut self, name: &'static str, data: &rmpv::Value) -> Option<T> {
+        self.deserialize_at(PathSegment::Field(name), data)
+    }
+
+    /// Deserialize an element of a list, tracking the index in the path for error messages
+    pub fn deserialize_index<T: Serializable<P>>(&mut self, idx: usize, data: &rmpv::Value) -> Option<T> {
+        self.deserialize_at(PathSegment::Index(idx), data)
+    }
+
+    /// Deserialize a key-value entry of a map, tracking the key in the path for error messages
+    pub fn deserialize_entry<K: Serializable<P>, V: Serializable<P>>(&mut self, key: &rmpv::Value, value: &rmpv::Value) -> Option<(K, V)> {
+        let key_name = key.to_string();
+        let key_result = self.deserialize_at(PathSegment::Key(key_name.clone()), key);
+        let value_result = self.deserialize_at(PathSegment::Key(key_name), value);
+        Some((key_result?, value_result?))
+    }
+
+    /// Deserialize the data of an object stored under its own key. Paths of errors inside the object start at the object.
+    pub(crate) fn deserialize_object<T: Serializable<P>>(&mut self, name: &'static str, key: u64, data: &rmpv::Value) -> Option<T> {
+        let outer_path = std::mem::replace(&mut self.path, Vec::new());
+        self.depth += 1;
+        let result = self.deserialize_at(PathSegment::Object(name, key), data);
+        self.depth -= 1;
+        self.path = outer_path;
+        result
+    }
+
+    /// Take the objects that were included in the data being deserialized, to be instanced into the project
+    pub(crate) fn take_data_objects(&mut self) -> P::Objects {
+        match &mut self.kind {
+            DeserializationContextKind::Data { objects } => std::mem::take(objects),
+            DeserializationContextKind::Local { .. } | DeserializationContextKind::Collab { .. } => P::Objects::default(),
+        }
+    }
+
+    /// Are we loading from a file that needs to be migrated to the current version of the project format?
+    pub(crate) fn is_migrating(&self) -> bool {
+        match &self.kind {
+            DeserializationContextKind::Local { file, .. } => file.is_migrating::<P>(),
+            DeserializationContextKind::Collab { .. } | DeserializationContextKind::Data { .. } => false,
         }
     }
 
@@ -66,6 +230,10 @@ pub struct SerializationContext<'a, P: Project> {
     kind: SerializationContextKind<'a, P>,
     /// The keys of the objects already stored
     stored: RefCell<HashSet<u64>>,
+    /// The number of levels of objects below the root object to include. Deeper objects are left out.
+    max_depth: Option<u32>,
+    /// The number of objects the value currently being serialized is nested in
+    depth: Cell<u32>,
 }
 
 impl<'a, P: Project> SerializationContext<'a, P> {
@@ -74,6 +242,8 @@ impl<'a, P: Project> SerializationContext<'a, P> {
         Self {
             kind: SerializationContextKind::Shallow,
             stored: RefCell::new(HashSet::new()),
+            max_depth: None,
+            depth: Cell::new(0),
         }
     }
 
@@ -83,6 +253,8 @@ impl<'a, P: Project> SerializationContext<'a, P> {
                 objects
             },
             stored: RefCell::new(HashSet::new()),
+            max_depth: None,
+            depth: Cell::new(0),
         }
     }
 
@@ -91,6 +263,21 @@ impl<'a, P: Project> SerializationContext<'a, P> {
         self
     }
 
+    pub(crate) fn with_max_depth(mut self, max_depth: Option<u32>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Mark an object as stored in the serialized data. Returns false if it was already stored.
+    pub(crate) fn store(&self, key: u64) -> bool {
+        self.stored.borrow_mut().insert(key)
+    }
+
+    /// Get the keys of all the objects stored in the serialized data
+    pub(crate) fn into_stored(self) -> HashSet<u64> {
+        self.stored.into_inner()
+    }
+
 }
 
 pub trait Serializable<P: Project>: Sized {
@@ -99,3 +286,173 @@ pub trait Serializable<P: Project>: Sized {
     fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self>;
 
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test_project::TestProject;
+
+    use super::{DeserializationContext, DeserializationError, Serializable, SerializationContext};
+
+    fn round_trip<T: Serializable<TestProject>>(value: &T) -> (rmpv::Value, Option<T>) {
+        let data = value.serialize(&SerializationContext::shallow());
+        let (value, errors) = deserialize::<T>(&data);
+        assert!(errors.is_empty(), "{:?}", errors);
+        (data, value)
+    }
+
+    fn deserialize<T: Serializable<TestProject>>(data: &rmpv::Value) -> (Option<T>, Vec<DeserializationError>) {
+        let mut context = DeserializationContext::<TestProject>::data();
+        let value = T::deserialize(data, &mut context);
+        (value, context.take_errors())
+    }
+
+    #[derive(crate::Serializable, Clone, Default, Debug, PartialEq)]
+    enum Shape {
+        #[default]
+        Empty,
+        Circle(f32),
+        Rect {
+            width: f32,
+            height: f32
+        }
+    }
+
+    #[derive(crate::Serializable, Default, Debug, PartialEq)]
+    struct Drawing {
+        name: String,
+        shape: Shape
+    }
+
+    #[test]
+    fn enum_variants_round_trip() {
+        let (data, shape) = round_trip(&Shape::Empty);
+        assert_eq!(data, rmpv::Value::from("Empty"));
+        assert_eq!(shape, Some(Shape::Empty));
+
+        let (data, shape) = round_trip(&Shape::Circle(2.5));
+        assert_eq!(data, rmpv::Value::Map(vec![("Circle".into(), rmpv::Value::Array(vec![2.5.into()]))]));
+        assert_eq!(shape, Some(Shape::Circle(2.5)));
+
+        let (_, shape) = round_trip(&Shape::Rect { width: 3.0, height: 4.0 });
+        assert_eq!(shape, Some(Shape::Rect { width: 3.0, height: 4.0 }));
+    }
+
+    #[test]
+    fn unknown_enum_variants_are_errors() {
+        let (shape, errors) = deserialize::<Shape>(&"Triangle".into());
+        assert_eq!(shape, None);
+        assert_eq!(errors[0].message(), "unknown variant `Triangle`");
+
+        // Structs holding the enum keep the rest of their data
+        let data = rmpv::Value::Map(vec![
+            ("name".into(), "Drawing".into()),
+            ("shape".into(), rmpv::Value::Map(vec![("Triangle".into(), rmpv::Value::Array(vec![1.into()]))]))
+        ]);
+        let (drawing, errors) = deserialize::<Drawing>(&data);
+        assert_eq!(drawing, Some(Drawing { name: "Drawing".to_owned(), shape: Shape::Empty }));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path(), "shape");
+    }
+
+    fn default_opacity() -> f32 {
+        1.0
+    }
+
+    #[derive(crate::Serializable, Default, Debug, PartialEq)]
+    struct Layer {
+        #[serialize(rename = "title", alias = "name")]
+        label: String,
+        #[serialize(default = default_opacity)]
+        opacity: f32,
+        #[serialize(skip_if = String::is_empty)]
+        notes: String
+    }
+
+    #[test]
+    fn renamed_fields_are_read_from_their_aliases() {
+        let (data, layer) = round_trip(&Layer { label: "Layer".to_owned(), opacity: 0.5, notes: String::new() });
+        assert_eq!(layer.unwrap().label, "Layer");
+        assert!(crate::rmpv_get(&data, "title").is_some());
+        assert!(crate::rmpv_get(&data, "label").is_none());
+
+        let data = rmpv::Value::Map(vec![
+            ("name".into(), "Old Layer".into()),
+            ("opacity".into(), 0.5.into())
+        ]);
+        let (layer, errors) = deserialize::<Layer>(&data);
+        assert!(errors.is_empty());
+        assert_eq!(layer.unwrap().label, "Old Layer");
+    }
+
+    #[test]
+    fn missing_fields_use_their_default() {
+        let data = rmpv::Value::Map(vec![("title".into(), "Layer".into())]);
+        let (layer, _) = deserialize::<Layer>(&data);
+        assert_eq!(layer.unwrap().opacity, 1.0);
+
+        // Invalid values fall back to the default too
+        let data = rmpv::Value::Map(vec![("opacity".into(), "opaque".into())]);
+        let (layer, errors) = deserialize::<Layer>(&data);
+        assert_eq!(layer.unwrap().opacity, 1.0);
+        assert_eq!(errors[0].path(), "opacity");
+    }
+
+    #[test]
+    fn skipped_fields_are_left_out() {
+        let (data, layer) = round_trip(&Layer { label: "Layer".to_owned(), opacity: 1.0, notes: String::new() });
+        assert!(crate::rmpv_get(&data, "notes").is_none());
+        assert_eq!(layer.unwrap().notes, "");
+
+        let (data, layer) = round_trip(&Layer { label: "Layer".to_owned(), opacity: 1.0, notes: "Notes".to_owned() });
+        assert!(crate::rmpv_get(&data, "notes").is_some());
+        assert_eq!(layer.unwrap().notes, "Notes");
+    }
+
+    #[derive(crate::Serializable, Default, Debug, PartialEq)]
+    #[serialize(compact)]
+    struct StrokePoint {
+        #[tag = 0]
+        x: f32,
+        #[tag = 1]
+        y: f32,
+        #[tag = 2]
+        pressure: f32,
+        #[no_serialize]
+        selected: bool
+    }
+
+    #[test]
+    fn compact_structs_are_stored_under_tags() {
+        let point = StrokePoint { x: 1.0, y: 2.0, pressure: 0.5, selected: true };
+        let (data, read) = round_trip(&point);
+        assert_eq!(data, rmpv::Value::Map(vec![
+            (0.into(), 1.0.into()),
+            (1.into(), 2.0.into()),
+            (2.into(), 0.5.into())
+        ]));
+        assert_eq!(read, Some(StrokePoint { selected: false, ..point }));
+    }
+
+    #[test]
+    fn compact_structs_read_older_data() {
+        // Written before `pressure` was added
+        let data = rmpv::Value::Map(vec![
+            (0.into(), 1.0.into()),
+            (1.into(), 2.0.into())
+        ]);
+        let (point, errors) = deserialize::<StrokePoint>(&data);
+        assert!(errors.is_empty());
+        assert_eq!(point, Some(StrokePoint { x: 1.0, y: 2.0, pressure: 0.0, selected: false }));
+
+        // Written before the struct was made compact
+        let data = rmpv::Value::Map(vec![
+            ("x".into(), 1.0.into()),
+            ("y".into(), 2.0.into()),
+            ("pressure".into(), 0.5.into())
+        ]);
+        let (point, errors) = deserialize::<StrokePoint>(&data);
+        assert!(errors.is_empty());
+        assert_eq!(point, Some(StrokePoint { x: 1.0, y: 2.0, pressure: 0.5, selected: false }));
+    }
+
+}
@@ -0,0 +1,75 @@
+
+use std::ops::{Deref, DerefMut};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::Project;
+use super::{DeserializationContext, Serializable, SerializationContext};
+
+/// A wrapper that serializes a value using its serde implementation, for types that implement serde but not `Serializable`.
+/// Fields can also be serialized this way using `#[serialize(with_serde)]`.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SerdeValue<T>(pub T);
+
+impl<T> SerdeValue<T> {
+
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+
+}
+
+impl<T: Serialize> SerdeValue<T> {
+
+    /// Serialize a value using its serde implementation
+    pub fn serialize_value(value: &T) -> rmpv::Value {
+        rmpv::ext::to_value(value).unwrap_or(rmpv::Value::Nil)
+    }
+
+}
+
+impl<T> Deref for SerdeValue<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for SerdeValue<T> {
+
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+
+}
+
+impl<T> From<T> for SerdeValue<T> {
+
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+
+}
+
+impl<P: Project, T: Serialize + DeserializeOwned> Serializable<P> for SerdeValue<T> {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        match rmpv::ext::from_value(data.clone()) {
+            Ok(value) => Some(Self(value)),
+            Err(error) => {
+                context.error(error.to_string());
+                None
+            }
+        }
+    }
+
+    fn serialize(&self, _context: &SerializationContext<P>) -> rmpv::Value {
+        Self::serialize_value(&self.0)
+    }
+
+}
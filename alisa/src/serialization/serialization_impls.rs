@@ -1,7 +1,7 @@
 
-use std::{collections::HashSet, hash::Hash};
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque}, hash::Hash, rc::Rc};
 
-use crate::{Ptr, Object, Project};
+use crate::{LoadingPtr, Ptr, Object, Project};
 use super::{Serializable, DeserializationContext, SerializationContext};
 
 macro_rules! number_serializable_impl {
@@ -9,8 +9,14 @@ macro_rules! number_serializable_impl {
         paste::paste! {
             impl<P: Project> Serializable<P> for $T {
 
-                fn deserialize(data: &rmpv::Value, _context: &mut DeserializationContext<P>) -> Option<Self> {
-                    data.[< as_ $N >]()?.try_into().ok()
+                fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+                    let Some(value) = data.[< as_ $N >]() else {
+                        return context.expected(stringify!($T), data);
+                    };
+                    value.try_into().ok().or_else(|| {
+                        context.error(format!("{} out of range for {}", value, stringify!($T)));
+                        None
+                    })
                 }
 
                 fn serialize(&self, _context: &SerializationContext<P>) -> rmpv::Value {
@@ -35,8 +41,11 @@ number_serializable_impl!(usize, u64);
 
 impl<P: Project> Serializable<P> for f32 {
 
-    fn deserialize(data: &rmpv::Value, _context: &mut DeserializationContext<P>) -> Option<Self> {
-        Some(data.as_f64()? as f32)
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        let Some(value) = data.as_f64() else {
+            return context.expected("f32", data);
+        };
+        Some(value as f32)
     }
 
     fn serialize(&self, _context: &SerializationContext<P>) -> rmpv::Value {
@@ -47,10 +56,50 @@ impl<P: Project> Serializable<P> for f32 {
 
 number_serializable_impl!(f64, f64);
 
+impl<P: Project> Serializable<P> for bool {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        let Some(value) = data.as_bool() else {
+            return context.expected("bool", data);
+        };
+        Some(value)
+    }
+
+    fn serialize(&self, _context: &SerializationContext<P>) -> rmpv::Value {
+        (*self).into()
+    }
+
+}
+
+impl<P: Project> Serializable<P> for char {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        let Some(value) = data.as_str() else {
+            return context.expected("char", data);
+        };
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(char), None) => Some(char),
+            _ => {
+                context.error(format!("expected a single character, got string of length {}", value.chars().count()));
+                None
+            }
+        }
+    }
+
+    fn serialize(&self, _context: &SerializationContext<P>) -> rmpv::Value {
+        self.to_string().into()
+    }
+
+}
+
 impl<P: Project> Serializable<P> for String {
 
-    fn deserialize(data: &rmpv::Value, _context: &mut DeserializationContext<P>) -> Option<Self> {
-        Some(data.as_str()?.to_owned())
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        let Some(value) = data.as_str() else {
+            return context.expected("string", data);
+        };
+        Some(value.to_owned())
     }
 
     fn serialize(&self, _context: &SerializationContext<P>) -> rmpv::Value {
@@ -62,8 +111,8 @@ impl<P: Project> Serializable<P> for String {
 impl<P: Project, T: Serializable<P>> Serializable<P> for Vec<T> {
 
     fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
-        let Some(arr) = data.as_array() else { return Some(Vec::new()); };
-        Some(arr.iter().filter_map(|element| T::deserialize(element, context)).collect())
+        let Some(arr) = data.as_array() else { return context.expected("array", data); };
+        Some(arr.iter().enumerate().filter_map(|(idx, element)| context.deserialize_index(idx, element)).collect())
     }
 
     fn serialize(&self, context: &SerializationContext<P>) -> rmpv::Value {
@@ -75,8 +124,8 @@ impl<P: Project, T: Serializable<P>> Serializable<P> for Vec<T> {
 impl<P: Project, T: Serializable<P> + Eq + Hash> Serializable<P> for HashSet<T> {
 
     fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
-        let Some(arr) = data.as_array() else { return Some(HashSet::new()); };
-        Some(arr.iter().filter_map(|element| T::deserialize(element, context)).collect())
+        let Some(arr) = data.as_array() else { return context.expected("array", data); };
+        Some(arr.iter().enumerate().filter_map(|(idx, element)| context.deserialize_index(idx, element)).collect())
     }
 
     fn serialize(&self, context: &SerializationContext<P>) -> rmpv::Value {
@@ -85,6 +134,154 @@ impl<P: Project, T: Serializable<P> + Eq + Hash> Serializable<P> for HashSet<T>
 
 }
 
+impl<P: Project, T: Serializable<P> + Ord> Serializable<P> for BTreeSet<T> {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        let Some(arr) = data.as_array() else { return context.expected("array", data); };
+        Some(arr.iter().enumerate().filter_map(|(idx, element)| context.deserialize_index(idx, element)).collect())
+    }
+
+    fn serialize(&self, context: &SerializationContext<P>) -> rmpv::Value {
+        rmpv::Value::Array(self.iter().map(|val| val.serialize(context)).collect())
+    }
+
+}
+
+impl<P: Project, T: Serializable<P>> Serializable<P> for VecDeque<T> {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        let Some(arr) = data.as_array() else { return context.expected("array", data); };
+        Some(arr.iter().enumerate().filter_map(|(idx, element)| context.deserialize_index(idx, element)).collect())
+    }
+
+    fn serialize(&self, context: &SerializationContext<P>) -> rmpv::Value {
+        rmpv::Value::Array(self.iter().map(|val| val.serialize(context)).collect())
+    }
+
+}
+
+impl<P: Project, T: Serializable<P>, const N: usize> Serializable<P> for [T; N] {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        let Some(arr) = data.as_array() else { return context.expected("array", data); };
+        if arr.len() != N {
+            context.error(format!("expected array of {} elements, got {}", N, arr.len()));
+            return None;
+        }
+        let elements = arr.iter().enumerate().map(|(idx, element)| context.deserialize_index(idx, element)).collect::<Option<Vec<T>>>()?;
+        elements.try_into().ok()
+    }
+
+    fn serialize(&self, context: &SerializationContext<P>) -> rmpv::Value {
+        rmpv::Value::Array(self.iter().map(|val| val.serialize(context)).collect())
+    }
+
+}
+
+// Maps are encoded as MessagePack maps, so keys do not need to be strings
+
+impl<P: Project, K: Serializable<P> + Eq + Hash, V: Serializable<P>> Serializable<P> for HashMap<K, V> {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        let Some(map) = data.as_map() else { return context.expected("map", data); };
+        Some(map.iter().filter_map(|(key, value)| context.deserialize_entry(key, value)).collect())
+    }
+
+    fn serialize(&self, context: &SerializationContext<P>) -> rmpv::Value {
+        rmpv::Value::Map(self.iter().map(|(key, value)| (key.serialize(context), value.serialize(context))).collect())
+    }
+
+}
+
+impl<P: Project, K: Serializable<P> + Ord, V: Serializable<P>> Serializable<P> for BTreeMap<K, V> {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        let Some(map) = data.as_map() else { return context.expected("map", data); };
+        Some(map.iter().filter_map(|(key, value)| context.deserialize_entry(key, value)).collect())
+    }
+
+    fn serialize(&self, context: &SerializationContext<P>) -> rmpv::Value {
+        rmpv::Value::Map(self.iter().map(|(key, value)| (key.serialize(context), value.serialize(context))).collect())
+    }
+
+}
+
+impl<P: Project, T: Serializable<P>> Serializable<P> for Option<T> {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        if data.is_nil() {
+            return Some(None);
+        }
+        T::deserialize(data, context).map(Some)
+    }
+
+    fn serialize(&self, context: &SerializationContext<P>) -> rmpv::Value {
+        match self {
+            Some(value) => value.serialize(context),
+            None => rmpv::Value::Nil,
+        }
+    }
+
+}
+
+impl<P: Project, T: Serializable<P>> Serializable<P> for Box<T> {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        T::deserialize(data, context).map(Box::new)
+    }
+
+    fn serialize(&self, context: &SerializationContext<P>) -> rmpv::Value {
+        self.as_ref().serialize(context)
+    }
+
+}
+
+impl<P: Project, T: Serializable<P>> Serializable<P> for Rc<T> {
+
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+        T::deserialize(data, context).map(Rc::new)
+    }
+
+    fn serialize(&self, context: &SerializationContext<P>) -> rmpv::Value {
+        self.as_ref().serialize(context)
+    }
+
+}
+
+macro_rules! tuple_serializable_impl {
+    ($N: literal; $($T: ident $idx: tt),+) => {
+        impl<P: Project, $($T: Serializable<P>),+> Serializable<P> for ($($T,)+) {
+
+            fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<P>) -> Option<Self> {
+                let Some(arr) = data.as_array() else { return context.expected("array", data); };
+                if arr.len() != $N {
+                    context.error(format!("expected array of {} elements, got {}", $N, arr.len()));
+                    return None;
+                }
+                Some(($(context.deserialize_index::<$T>($idx, &arr[$idx])?,)+))
+            }
+
+            fn serialize(&self, context: &SerializationContext<P>) -> rmpv::Value {
+                rmpv::Value::Array(vec![$(self.$idx.serialize(context)),+])
+            }
+
+        }
+    };
+}
+
+tuple_serializable_impl!(1; A 0);
+tuple_serializable_impl!(2; A 0, B 1);
+tuple_serializable_impl!(3; A 0, B 1, C 2);
+tuple_serializable_impl!(4; A 0, B 1, C 2, D 3);
+tuple_serializable_impl!(5; A 0, B 1, C 2, D 3, E 4);
+tuple_serializable_impl!(6; A 0, B 1, C 2, D 3, E 4, F 5);
+tuple_serializable_impl!(7; A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+tuple_serializable_impl!(8; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+tuple_serializable_impl!(9; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+tuple_serializable_impl!(10; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+tuple_serializable_impl!(11; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+tuple_serializable_impl!(12; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
 impl<P: Project> Serializable<P> for () {
 
     fn serialize(&self, _context: &SerializationContext<P>) -> rmpv::Value {
@@ -99,8 +296,15 @@ impl<P: Project> Serializable<P> for () {
 
 impl<O: Object> Serializable<O::Project> for Ptr<O> {
 
-    fn deserialize(data: &rmpv::Value, _context: &mut DeserializationContext<O::Project>) -> Option<Self> {
-        data.as_u64().map(Self::from_key)
+    fn deserialize(data: &rmpv::Value, context: &mut DeserializationContext<O::Project>) -> Option<Self> {
+        let Some(ptr) = data.as_u64().map(Self::from_key) else {
+            return context.expected("object key", data);
+        };
+        // When migrating a file, load the object being pointed to so that it gets migrated too
+        if !ptr.is_null() && context.loads_ptr_targets() {
+            LoadingPtr::<O>::deserialize(data, context);
+        }
+        Some(ptr)
     }
 
     fn serialize(&self, _context: &SerializationContext<O::Project>) -> rmpv::Value {
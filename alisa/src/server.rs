@@ -1,10 +1,12 @@
 
-use std::{collections::HashMap, fmt::Debug, path::Path};
+use std::{collections::{HashMap, HashSet}, fmt::Debug, path::Path};
 
-use crate::{Client, Serializable, Project, SerializationContext};
+use crate::{already_encoded, Client, CompactionReport, DeserializationError, Error, GarbageReport, Object, Serializable, Project, Ptr, SerializationContext, Storage};
 
 struct ServerClient {
-    to_send: Vec<rmpv::Value>
+    to_send: Vec<rmpv::Value>,
+    /// The keys of the objects the client has loaded
+    held: HashSet<u64>
 }
 
 pub struct Server<P: Project> {
@@ -12,7 +14,9 @@ pub struct Server<P: Project> {
     client: Client<P>,
     context: P::Context,
     curr_client_id: u64,
-    clients: HashMap<ClientId, ServerClient>
+    clients: HashMap<ClientId, ServerClient>,
+    /// The last problem encountered while saving to the project file, if it wasn't taken yet
+    save_error: Option<Error>
 }
 
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Default)]
@@ -40,13 +44,38 @@ impl<P: Project> Serializable<P> for ClientId {
 
 impl<P: Project> Server<P> {
 
-    pub fn new<PathRef: AsRef<Path>>(path: PathRef, context: P::Context) -> Option<Self> {
+    /// Start a server for a project file. See `Client::local`.
+    pub fn new<PathRef: AsRef<Path>>(path: PathRef, context: P::Context) -> Result<Self, Error> {
         let client = Client::local(path)?;
-        Some(Self {
+        Ok(Self {
             client,
             context,
             curr_client_id: 1,
-            clients: HashMap::new()
+            clients: HashMap::new(),
+            save_error: None
+        })
+    }
+
+    /// Start a server for a new, empty project kept in memory. See `Client::in_memory`.
+    pub fn in_memory(context: P::Context) -> Self {
+        Self {
+            client: Client::in_memory(),
+            context,
+            curr_client_id: 1,
+            clients: HashMap::new(),
+            save_error: None
+        }
+    }
+
+    /// Start a server for a project kept in a custom storage. See `Client::with_storage`.
+    pub fn with_storage<S: Storage + 'static>(storage: S, context: P::Context) -> Result<Self, Error> {
+        let client = Client::with_storage(storage)?;
+        Ok(Self {
+            client,
+            context,
+            curr_client_id: 1,
+            clients: HashMap::new(),
+            save_error: None
         })
     }
 
@@ -54,13 +83,14 @@ impl<P: Project> Server<P> {
         let id = ClientId(self.curr_client_id);
         self.curr_client_id += 1;
 
-        self.clients.insert(id, ServerClient {
-            to_send: Vec::new()
-        });
-
         let storing_context = SerializationContext::deep(&self.client.objects);
         let project_data = self.client.project.serialize(&storing_context); 
 
+        self.clients.insert(id, ServerClient {
+            to_send: Vec::new(),
+            held: storing_context.into_stored()
+        });
+
         (id, rmpv::Value::Map(vec![
             ("id".into(), id.0.into()),
             ("project".into(), project_data)
@@ -88,6 +118,8 @@ impl<P: Project> Server<P> {
         let mut data = None;
         let mut object = "";
         let mut load_key = 0;
+        let mut load_depth = None;
+        let mut objects: &[rmpv::Value] = &[];
 
         for (key, val) in msg {
             match key.as_str()? {
@@ -106,6 +138,12 @@ impl<P: Project> Server<P> {
                 "object" => {
                     object = val.as_str()?;
                 },
+                "depth" => {
+                    load_depth = Some(val.as_u64()?.try_into().ok()?);
+                },
+                "objects" => {
+                    objects = val.as_array()?;
+                },
                 _ => {}
             } 
         }
@@ -138,32 +176,128 @@ impl<P: Project> Server<P> {
                 for object_kind in P::OBJECTS {
                     if object_kind.name == object {
                         let local = self.client.kind.as_local().unwrap();
-                        local.dyn_load(&object_kind, &mut self.client.objects, load_key);
-                        let data = (object_kind.serialize_object)(&mut self.client.objects, load_key);
+                        let warnings = local.dyn_load(&object_kind, &mut self.client.objects, load_key, load_depth);
+                        self.client.load_warnings.extend(warnings);
+                        let data = (object_kind.serialize_object)(&mut self.client.objects, load_key, load_depth);
                         if let Some(data) = data {
-                            self.send(client_id, rmpv::Value::Map(vec![
+                            if let Some(client) = self.clients.get_mut(&client_id) {
+                                client.held.insert(load_key);
+                            }
+                            let mut response = vec![
                                 ("type".into(), "load".into()),
                                 ("object".into(), object.into()),
                                 ("key".into(), load_key.into()),
                                 ("data".into(), data)
+                            ];
+                            if let Some(depth) = load_depth {
+                                response.push(("depth".into(), depth.into()));
+                            }
+                            self.send(client_id, rmpv::Value::Map(response));
+                        } else {
+                            self.send(client_id, rmpv::Value::Map(vec![
+                                ("type".into(), "load_failed".into()),
+                                ("object".into(), object.into()),
+                                ("key".into(), load_key.into())
                             ]));
                         }
                         break;
                     }
                 }
             },
+            "load_many" => {
+                self.load_many(client_id, objects);
+            },
+            "unload" => {
+                if let Some(client) = self.clients.get_mut(&client_id) {
+                    client.held.remove(&load_key);
+                }
+            },
             _ => {}
         }
 
-        self.client.tick(&mut self.context);
+        if let Some(error) = self.client.tick(&mut self.context).take_save_error() {
+            self.save_error = Some(error);
+        }
 
         Some(())
     }
 
+    /// Respond to a batched load request. All the objects are sent in a single message, so objects included in several of them are only encoded once.
+    fn load_many(&mut self, client_id: ClientId, requests: &[rmpv::Value]) {
+        let mut to_send = Vec::new();
+        let mut missing = Vec::new();
+        for request in requests {
+            let Some([object, key]) = request.as_array().map(Vec::as_slice) else { continue; };
+            let (Some(object), Some(key)) = (object.as_str(), key.as_u64()) else { continue; };
+            let Some(object_kind) = P::OBJECTS.iter().find(|object_kind| object_kind.name == object) else {
+                missing.push(request.clone());
+                continue;
+            };
+            let local = self.client.kind.as_local().expect("server should only use local client.");
+            let warnings = local.dyn_load(object_kind, &mut self.client.objects, key, None);
+            self.client.load_warnings.extend(warnings);
+            to_send.push((object_kind, key));
+        }
+
+        let storing_context = SerializationContext::deep(&self.client.objects);
+        let mut loaded = Vec::new();
+        for (object_kind, key) in to_send {
+            // If the object was already included in another one, don't encode it again
+            if !storing_context.store(key) {
+                loaded.push(rmpv::Value::Array(vec![object_kind.name.into(), key.into(), already_encoded()]));
+                continue;
+            }
+            match (object_kind.serialize_object_with)(&self.client.objects, key, &storing_context) {
+                Some(data) => loaded.push(rmpv::Value::Array(vec![object_kind.name.into(), key.into(), data])),
+                None => missing.push(rmpv::Value::Array(vec![object_kind.name.into(), key.into()])),
+            }
+        }
+
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            client.held.extend(storing_context.into_stored());
+        }
+        self.send(client_id, rmpv::Value::Map(vec![
+            ("type".into(), "load_many".into()),
+            ("objects".into(), rmpv::Value::Array(loaded)),
+            ("missing".into(), rmpv::Value::Array(missing))
+        ]));
+    }
+
     pub fn project(&self) -> &P {
         &self.client.project
     }
 
+    /// Delete all the objects in the project file that can't be reached from the project. See `Client::collect_garbage`.
+    pub fn collect_garbage(&mut self) -> Result<GarbageReport, Error> {
+        self.client.collect_garbage()
+    }
+
+    /// Compact the project file, freeing the space left behind by deleted objects. See `Client::compact`.
+    pub fn compact(&mut self) -> Result<CompactionReport, Error> {
+        self.client.compact()
+    }
+
+    /// Save a copy of the project into a new file. See `Client::save_copy`.
+    pub fn save_copy<PathRef: AsRef<Path>>(&mut self, path: PathRef) -> Result<(), Error> {
+        self.client.save_copy(path)
+    }
+
+    /// Take the last problem that stopped changes from being saved to the project file, if any happened since the last call.
+    /// The changes that couldn't be saved are kept in memory and saving them is retried after the next message. See `ChangeSet::save_error`.
+    pub fn take_save_error(&mut self) -> Option<Error> {
+        self.save_error.take()
+    }
+
+    /// Take the problems encountered while loading data from the project file since the last call
+    pub fn take_load_warnings(&mut self) -> Vec<DeserializationError> {
+        self.client.take_load_warnings()
+    }
+
+    /// Does a client have an object loaded? Clients tell the server when they unload objects.
+    pub fn client_holds<O: Object<Project = P>>(&self, client: ClientId, ptr: Ptr<O>) -> bool {
+        self.clients.get(&client).map(|client| client.held.contains(&ptr.key)).unwrap_or(false)
+    }
+
     pub fn get_msgs_to_send(&mut self, client: ClientId) -> Option<&mut Vec<rmpv::Value>> {
         Some(&mut self.clients.get_mut(&client)?.to_send)
     }
@@ -0,0 +1,154 @@
+
+//! A small project used by the tests: a tree of named folders below the project, along with a counter on the project itself.
+
+// The operation macros are written to be used outside of this crate, where their imports are needed
+#![allow(unused_imports, unused_variables)]
+
+use std::path::PathBuf;
+
+use crate::{Client, Object, Ptr};
+
+#[derive(Default)]
+pub struct TestObjects {
+    pub folders: crate::ObjList<Folder>,
+}
+
+#[derive(crate::Serializable, Default)]
+#[project(TestProject)]
+pub struct TestProject {
+    pub n: i32,
+    pub folders: crate::UnorderedChildList<Folder>
+}
+
+impl crate::Project for TestProject {
+    type Context = ();
+    type Objects = TestObjects;
+
+    fn empty() -> Self {
+        Self::default()
+    }
+
+    fn create_default(&mut self) {
+
+    }
+
+    // Version 0 files are migrated without any changes to their data
+    const VERSION: u64 = 1;
+
+    const OBJECTS: &'static [crate::ObjectKind<Self>] = &[
+        crate::ObjectKind::from::<Folder>()
+    ];
+
+    const OPERATIONS: &'static [crate::OperationKind<Self>] = &[
+        crate::OperationKind::from::<SetN>(),
+        crate::OperationKind::from::<CreateFolder>(),
+        crate::OperationKind::from::<DeleteFolder>(),
+        crate::OperationKind::from::<TransferFolder>(),
+        crate::OperationKind::from::<SetFolderName>(),
+    ];
+}
+
+crate::project_set_property_operation!(TestProject, n, i32);
+
+#[derive(Clone, crate::Serializable, Default)]
+#[project(TestProject)]
+pub struct Folder {
+    pub parent: Ptr<Folder>,
+    pub name: String,
+    pub folders: crate::UnorderedChildList<Folder>
+}
+
+impl crate::Object for Folder {
+    type Project = TestProject;
+
+    const NAME: &'static str = "Folder";
+
+    fn list(objects: &TestObjects) -> &crate::ObjList<Self> {
+        &objects.folders
+    }
+
+    fn list_mut(objects: &mut TestObjects) -> &mut crate::ObjList<Self> {
+        &mut objects.folders
+    }
+}
+
+impl crate::TreeObj for Folder {
+    type ParentPtr = Ptr<Folder>;
+    type ChildList = crate::UnorderedChildList<Folder>;
+    type TreeData = FolderTreeData;
+
+    fn child_list<'a>(parent: Self::ParentPtr, context: &'a crate::ProjectContext<TestProject>) -> Option<&'a Self::ChildList> {
+        if parent.is_null() {
+            return Some(&context.project().folders);
+        }
+        Some(&context.obj_list().get(parent)?.folders)
+    }
+
+    fn child_list_mut<'a>(parent: Self::ParentPtr, context: &'a mut crate::ProjectContextMut<TestProject>) -> Option<&'a mut Self::ChildList> {
+        if parent.is_null() {
+            return Some(&mut context.project_mut().folders);
+        }
+        Some(&mut context.obj_list_mut().get_mut(parent)?.folders)
+    }
+
+    fn parent(&self) -> Self::ParentPtr {
+        self.parent
+    }
+
+    fn parent_mut(&mut self) -> &mut Self::ParentPtr {
+        &mut self.parent
+    }
+
+    fn instance(data: &Self::TreeData, ptr: Ptr<Self>, parent: Self::ParentPtr, recorder: &mut crate::Recorder<TestProject>) {
+        let object = Self {
+            parent,
+            name: data.name.clone(),
+            folders: data.folders.instance(ptr, recorder),
+        };
+        Self::add(recorder, ptr, object);
+    }
+
+    fn destroy(&self, recorder: &mut crate::Recorder<TestProject>) {
+        self.folders.destroy(recorder);
+    }
+
+    fn collect_data(&self, objects: &TestObjects) -> Self::TreeData {
+        FolderTreeData {
+            name: self.name.clone(),
+            folders: self.folders.collect_data(objects),
+        }
+    }
+}
+
+#[derive(crate::Serializable, Default)]
+#[project(TestProject)]
+pub struct FolderTreeData {
+    pub name: String,
+    pub folders: crate::UnorderedChildListTreeData<Folder>
+}
+
+crate::tree_object_operations!(Folder);
+crate::object_set_property_operation!(Folder, name, String);
+
+/// Create a folder with the given name in a parent folder, or in the project if the parent is null
+pub(crate) fn create_folder(client: &Client<TestProject>, parent: Ptr<Folder>, name: &str) -> Ptr<Folder> {
+    let ptr = client.next_ptr().unwrap();
+    client.perform(&mut crate::Action::new(), CreateFolder {
+        ptr,
+        parent,
+        idx: (),
+        data: FolderTreeData {
+            name: name.to_owned(),
+            folders: Default::default()
+        }
+    });
+    ptr
+}
+
+/// A path in the temporary directory that no file exists at
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("alisa_test_{}_{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
@@ -32,6 +32,8 @@ macro_rules! tree_object_creation_operations {
                 type Inverse = [< Delete $object:camel >];
 
                 const NAME: &'static str = stringify!([< Create $object:camel >]);
+                // The tree data might reference the new object's children through `LoadingPtr`s
+                const INCLUDE_OBJECTS: bool = true;
 
                 fn perform(&self, recorder: &mut ::alisa::Recorder<Self::Project>) {
                     use ::alisa::TreeObj;
@@ -9,6 +9,17 @@ pub fn rmpv_get<'v>(value: &'v rmpv::Value, key: &str) -> Option<&'v rmpv::Value
     None
 }
 
+/// Get the value stored under an integer key, as used by the compact encoding
+pub fn rmpv_get_tag(value: &rmpv::Value, tag: u64) -> Option<&rmpv::Value> {
+    let map = value.as_map()?;
+    for (map_key, val) in map {
+        if map_key.as_u64() == Some(tag) {
+            return Some(val);
+        }
+    }
+    None
+}
+
 pub fn rmpv_encode(data: &rmpv::Value) -> Option<Vec<u8>> {
     let mut bytes = Vec::new();
     rmpv::encode::write_value(&mut bytes, data).ok()?;